@@ -0,0 +1,26 @@
+//! Exposes build information as vergen-style environment variables for `dyns --version`.
+
+use std::{path::Path, process::Command};
+
+use time::{macros::format_description, OffsetDateTime};
+
+fn output_of(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    let sha = output_of("git", &["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let rustc_version = output_of(&rustc, &["--version"]).unwrap_or_else(|| "unknown".into());
+    let date = OffsetDateTime::now_utc().format(format_description!("[year]-[month]-[day]")).unwrap();
+
+    println!("cargo:rustc-env=VERGEN_GIT_SHA={}", sha);
+    println!("cargo:rustc-env=VERGEN_BUILD_DATE={}", date);
+    println!("cargo:rustc-env=VERGEN_RUSTC_SEMVER={}", rustc_version);
+    for path in [".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! The local wall-clock time, for update windows and dated log file names.
+
+/// The broken-down local time, like libc's `struct tm` with the year and month as people count them.
+#[derive(Clone, Copy, Debug)]
+pub struct LocalTime {
+    pub year: i32,
+    /// 1 to 12.
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    /// Days since Sunday.
+    pub weekday: u32,
+}
+
+#[cfg(unix)]
+pub fn local_now() -> LocalTime {
+    // `time` refuses to look up the local offset once threads exist, so ask libc directly.
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    LocalTime {
+        year: tm.tm_year + 1900,
+        month: tm.tm_mon as u32 + 1,
+        day: tm.tm_mday as u32,
+        hour: tm.tm_hour as u32,
+        minute: tm.tm_min as u32,
+        weekday: tm.tm_wday as u32,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn local_now() -> LocalTime {
+    let now = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+    LocalTime {
+        year: now.year(),
+        month: u8::from(now.month()) as u32,
+        day: now.day() as u32,
+        hour: now.hour() as u32,
+        minute: now.minute() as u32,
+        weekday: now.weekday().number_days_from_sunday() as u32,
+    }
+}
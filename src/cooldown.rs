@@ -0,0 +1,18 @@
+//! Remembers when each record was last written, so `min_update_interval_secs` can hold off
+//! writes to a record that keep coming too fast, e.g. from a bouncing `contents_command`.
+
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+
+static LAST_WRITE: Mutex<Option<HashMap<String, Instant>>> = Mutex::new(None);
+
+/// How much longer `key` has to wait before it may be written again.
+pub fn remaining(key: &str, interval: Duration) -> Option<Duration> {
+    let last_write = LAST_WRITE.lock().unwrap();
+    let written = last_write.as_ref()?.get(key)?;
+    interval.checked_sub(written.elapsed()).filter(|left| !left.is_zero())
+}
+
+/// Notes a successful write of `key`.
+pub fn wrote(key: &str) {
+    LAST_WRITE.lock().unwrap().get_or_insert_with(HashMap::new).insert(key.to_string(), Instant::now());
+}
@@ -0,0 +1,156 @@
+use std::{ffi::CString, fs, io, os::unix::io::AsRawFd, path::{Path, PathBuf}, sync::OnceLock};
+
+use anyhow::Context;
+
+/// Detaches from the controlling terminal with the classic double fork.
+///
+/// Must be called before any threads are spawned (e.g. by the HTTP client).
+pub fn daemonize() -> anyhow::Result<()> {
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to start a new session");
+    }
+    fork_and_exit_parent()?;
+
+    unsafe { libc::umask(0o022) };
+    std::env::set_current_dir("/")?;
+
+    let null = fs::OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    for fd in 0..=2 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to redirect standard streams");
+        }
+    }
+    Ok(())
+}
+
+/// The path of the live [`PidFile`], for the signal handler to remove it.
+static PID_FILE_PATH: OnceLock<CString> = OnceLock::new();
+
+/// Holds our PID in a file for as long as it lives, so `dyns trigger` can find us.
+///
+/// The file is removed when this is dropped, and also when SIGTERM or SIGINT end the process, which
+/// skips destructors. Removing it fails if privileges were dropped to a user who can't write to its
+/// directory; [`trigger_update`] then notices the PID is no longer ours.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        if let Ok(c_path) = CString::new(path.as_os_str().as_encoded_bytes()) {
+            if PID_FILE_PATH.set(c_path).is_ok() {
+                for signal in [libc::SIGTERM, libc::SIGINT] {
+                    unsafe {
+                        libc::signal(signal, remove_pid_file_and_die as extern "C" fn(libc::c_int) as libc::sighandler_t);
+                    }
+                }
+            }
+        }
+        Ok(Self { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("Failed to remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Only calls async-signal-safe functions, then dies of `signal` as if it had never been caught.
+extern "C" fn remove_pid_file_and_die(signal: libc::c_int) {
+    if let Some(path) = PID_FILE_PATH.get() {
+        unsafe { libc::unlink(path.as_ptr()) };
+    }
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// Asks the daemon whose PID is in `pid_file` for an immediate update pass, returning its PID.
+pub fn trigger_update(pid_file: &Path) -> anyhow::Result<libc::pid_t> {
+    let text = fs::read_to_string(pid_file)
+        .with_context(|| format!("Failed to read PID file {}; is dyns running with --pid-file or --daemonize?", pid_file.display()))?;
+    let pid: libc::pid_t = text.trim().parse().with_context(|| format!("PID file {} doesn't contain a PID", pid_file.display()))?;
+    // A PID file left behind by a crash may name an unrelated process by now, which SIGUSR2 would
+    // most likely kill. Without /proc there is nothing to check against.
+    match fs::read_to_string(format!("/proc/{}/comm", pid)) {
+        Ok(comm) if comm.trim_end() != "dyns" => {
+            anyhow::bail!("PID {} from {} belongs to {}, not dyns; the PID file is stale", pid, pid_file.display(), comm.trim_end())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && Path::new("/proc/self").exists() => {
+            anyhow::bail!("dyns (PID {} from {}) isn't running anymore; the PID file is stale", pid, pid_file.display())
+        }
+        _ => (),
+    }
+    if unsafe { libc::kill(pid, libc::SIGUSR2) } < 0 {
+        return Err(io::Error::last_os_error()).with_context(|| format!("Failed to signal dyns (PID {})", pid));
+    }
+    Ok(pid)
+}
+
+fn fork_and_exit_parent() -> anyhow::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("Failed to fork"),
+        0 => Ok(()),
+        _ => std::process::exit(0),
+    }
+}
+
+/// Whom to run as once the config and log files are open.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct RunAs {
+    pub user: String,
+    /// Defaults to the user's primary group.
+    pub group: Option<String>,
+}
+
+/// Permanently switches to the given unprivileged user and group.
+///
+/// Must be called before any threads are spawned: the kernel keeps credentials per thread, and
+/// only the C library's wrappers bring the other threads along, which not every libc does.
+pub fn drop_privileges(run_as: &RunAs) -> anyhow::Result<()> {
+    let user = CString::new(run_as.user.as_str())?;
+    let passwd = unsafe { libc::getpwnam(user.as_ptr()) };
+    if passwd.is_null() {
+        anyhow::bail!("No such user {}", run_as.user);
+    }
+    let (uid, mut gid) = unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) };
+    if let Some(group) = &run_as.group {
+        let name = CString::new(group.as_str())?;
+        let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+        if entry.is_null() {
+            anyhow::bail!("No such group {}", group);
+        }
+        gid = unsafe { (*entry).gr_gid };
+    }
+
+    // Supplementary groups first, while we still may; then the group, since after
+    // setuid we couldn't change it anymore.
+    if unsafe { libc::initgroups(user.as_ptr(), gid as _) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to set supplementary groups");
+    }
+    if unsafe { libc::setgid(gid) } != 0 {
+        return Err(io::Error::last_os_error()).with_context(|| format!("Failed to switch to group {}", gid));
+    }
+    if unsafe { libc::setuid(uid) } != 0 {
+        return Err(io::Error::last_os_error()).with_context(|| format!("Failed to switch to user {}", run_as.user));
+    }
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        anyhow::bail!("Still able to regain root after dropping privileges");
+    }
+    log::info!("Dropped privileges, now running as {} ({}:{})", run_as.user, uid, gid);
+    Ok(())
+}
+
+/// Whether the current user may create files in the directory of `path`.
+pub fn can_create_in(path: &Path) -> bool {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Ok(dir) = CString::new(dir.as_os_str().as_encoded_bytes()) else { return false };
+    unsafe { libc::access(dir.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+}
@@ -0,0 +1,46 @@
+//! Collapses identical log messages that repeat every cycle, e.g. while Cloudflare is down,
+//! into one line per [`WINDOW`] saying how often they happened.
+
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+
+use log::Level;
+
+/// How long repeats of a message are held back before they're summarized.
+const WINDOW: Duration = Duration::from_secs(10 * 60);
+
+struct Seen {
+    message: String,
+    logged_at: Instant,
+    suppressed: u32,
+}
+
+static SEEN: Mutex<Option<HashMap<String, Seen>>> = Mutex::new(None);
+
+/// Logs `message` unless it's what was last logged for `key` within [`WINDOW`].
+pub fn log(level: Level, key: &str, message: String) {
+    let mut seen = SEEN.lock().unwrap();
+    let seen = seen.get_or_insert_with(HashMap::new);
+    match seen.get_mut(key) {
+        Some(last) if last.message == message && last.logged_at.elapsed() < WINDOW => last.suppressed += 1,
+        Some(last) if last.message == message => {
+            log::log!(level, "{} (repeated {} times in the last {}m)", message, last.suppressed + 1, last.logged_at.elapsed().as_secs() / 60);
+            last.logged_at = Instant::now();
+            last.suppressed = 0;
+        }
+        last => {
+            if let Some(last) = last.filter(|last| last.suppressed > 0) {
+                log::log!(level, "Previous message repeated {} more times: {}", last.suppressed, last.message);
+            }
+            log::log!(level, "{}", message);
+            seen.insert(key.to_string(), Seen { message, logged_at: Instant::now(), suppressed: 0 });
+        }
+    }
+}
+
+/// Forgets about `key` once things work again, so the next failure is logged right away.
+pub fn clear(key: &str) {
+    let mut seen = SEEN.lock().unwrap();
+    if let Some(last) = seen.as_mut().and_then(|seen| seen.remove(key)).filter(|last| last.suppressed > 0) {
+        log::info!("Recovered after {} more repeats of: {}", last.suppressed, last.message);
+    }
+}
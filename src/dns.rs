@@ -0,0 +1,98 @@
+//! Just enough of a DNS client to ask a resolver for the A or AAAA records of a name.
+
+use std::{net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket}, time::Duration};
+
+use crate::error::DynsError;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+fn invalid(message: &str) -> DynsError {
+    DynsError::Dns(message.to_string())
+}
+
+/// Returns the addresses `resolver` answers for `name`, looking up AAAA records if `ipv6` is set.
+pub fn resolve(resolver: SocketAddr, name: &str, ipv6: bool, timeout: Duration) -> Result<Vec<IpAddr>, DynsError> {
+    let id = fastrand::u16(..);
+    let qtype = if ipv6 { TYPE_AAAA } else { TYPE_A };
+
+    let mut query = Vec::with_capacity(512);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(DynsError::Dns(format!("Invalid DNS name {}", name)));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    let bind: SocketAddr = if resolver.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { (Ipv6Addr::UNSPECIFIED, 0).into() };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(resolver)?;
+    socket.send(&query)?;
+
+    let mut response = [0; 4096];
+    loop {
+        let len = socket.recv(&mut response)?;
+        let response = &response[..len];
+        if response.len() < 12 || response[..2] != id.to_be_bytes() {
+            // Not an answer to our query, keep waiting.
+            continue;
+        }
+        return parse_answers(response, qtype);
+    }
+}
+
+fn parse_answers(response: &[u8], qtype: u16) -> Result<Vec<IpAddr>, DynsError> {
+    let u16_at = |at: usize| -> Result<u16, DynsError> {
+        response.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or_else(|| invalid("Truncated DNS response"))
+    };
+    let rcode = response[3] & 0x0f;
+    if rcode != 0 {
+        return Err(invalid(&format!("Resolver answered with error code {}", rcode)));
+    }
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(response, at)? + 4;
+    }
+    let mut addresses = Vec::new();
+    for _ in 0..answers {
+        at = skip_name(response, at)?;
+        let (rtype, rdlength) = (u16_at(at)?, u16_at(at + 8)? as usize);
+        let rdata = response.get(at + 10..at + 10 + rdlength).ok_or_else(|| invalid("Truncated DNS response"))?;
+        match (rtype, rdata.len()) {
+            (TYPE_A, 4) if qtype == TYPE_A => addresses.push(IpAddr::V4(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]))),
+            (TYPE_AAAA, 16) if qtype == TYPE_AAAA => {
+                let octets: [u8; 16] = rdata.try_into().unwrap();
+                addresses.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            // CNAMEs and friends along the way.
+            _ => (),
+        }
+        at += 10 + rdlength;
+    }
+    Ok(addresses)
+}
+
+/// Returns the offset right after the (possibly compressed) name starting at `at`.
+fn skip_name(response: &[u8], mut at: usize) -> Result<usize, DynsError> {
+    loop {
+        let len = *response.get(at).ok_or_else(|| invalid("Truncated DNS response"))?;
+        match len {
+            0 => return Ok(at + 1),
+            // A compression pointer ends the name.
+            len if len & 0xc0 == 0xc0 => return Ok(at + 2),
+            len => at += 1 + len as usize,
+        }
+    }
+}
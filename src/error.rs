@@ -0,0 +1,83 @@
+//! The kinds of failure callers may need to tell apart, e.g. to pick an exit code or skip a
+//! backoff. The HTTP client, the DNS providers, the resolver and the Cloudflare calls all return
+//! them; only `main` wraps them into `anyhow` chains, where context added on the way up doesn't
+//! hide them from [`kind`].
+
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum DynsError {
+    /// The config couldn't be read, parsed or doesn't make sense.
+    Config(String),
+    /// Cloudflare answered 401 or 403, or reported the API token as not active.
+    Auth { what: String, reason: String },
+    /// None of the IP providers could be reached at all, e.g. because the link is down or DNS
+    /// doesn't resolve.
+    Network,
+    /// Cloudflare refused a request; `code` is the first error code it gave, if any, and `message`
+    /// has all of them.
+    Api { what: String, code: Option<u32>, message: String },
+    /// Cloudflare answered with a server error or something that isn't its JSON API, e.g. an HTML
+    /// error page during an incident. Unlike other errors this is expected to go away on its own.
+    Unavailable { status: u16 },
+    /// The IP providers answered, but none with an address we may publish.
+    IpDetection(String),
+    /// A request couldn't be sent or got no complete response, e.g. because it timed out.
+    Http(isahc::Error),
+    /// A record can't be updated the way it is configured, e.g. it's missing from the zone.
+    Record(String),
+    /// A helper command like `nsupdate` or a `contents_command` couldn't be run or failed.
+    Command { what: String, reason: String },
+    /// The resolver answered something that isn't a usable DNS response.
+    Dns(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for DynsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(message) => write!(f, "{}", message),
+            Self::Auth { what, reason } => write!(f, "Cloudflare rejected the credentials while trying to {} ({})", what, reason),
+            Self::Network => write!(f, "The network appears to be down, no IP provider could be reached"),
+            Self::Api { what, code: Some(code), message } => write!(f, "Failed to {}: {} (code {})", what, message, code),
+            Self::Api { what, code: None, message } => write!(f, "Failed to {}: {}", what, message),
+            Self::Unavailable { status } => write!(f, "Cloudflare appears to be having issues (HTTP {}), will retry later", status),
+            Self::IpDetection(message) => write!(f, "Could not detect the IP address: {}", message),
+            // isahc keeps the interesting part, e.g. what curl said, in the source.
+            Self::Http(e) => match std::error::Error::source(e) {
+                Some(source) => write!(f, "HTTP request failed: {}: {}", e, source),
+                None => write!(f, "HTTP request failed: {}", e),
+            },
+            Self::Record(message) | Self::Dns(message) => write!(f, "{}", message),
+            Self::Command { what, reason } => write!(f, "{} failed: {}", what, reason),
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DynsError {}
+
+impl From<isahc::Error> for DynsError {
+    fn from(error: isahc::Error) -> Self {
+        Self::Http(error)
+    }
+}
+
+impl From<io::Error> for DynsError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl DynsError {
+    /// Whether this happened before talking to the server, as opposed to e.g. a bad response.
+    pub fn is_network(&self) -> bool {
+        use isahc::error::ErrorKind;
+        matches!(self, Self::Http(e) if matches!(e.kind(), ErrorKind::ConnectionFailed | ErrorKind::NameResolution | ErrorKind::Timeout))
+    }
+}
+
+/// The [`DynsError`] at the root of `error`, if there is one.
+pub fn kind(error: &anyhow::Error) -> Option<&DynsError> {
+    error.downcast_ref()
+}
@@ -0,0 +1,91 @@
+//! A tiny HTTP endpoint for orchestrators: `GET /health` answers 200 while recent update passes
+//! went well and 503 once they failed often enough, see [`Thresholds`]. `GET /metrics` serves
+//! [`crate::metrics`], and `GET /status` the recent outcomes of every record as JSON.
+
+use std::{collections::VecDeque, io::{BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, sync::Mutex, time::Duration};
+
+use crate::history::History;
+
+/// When the service counts as unhealthy. Either limit being reached is enough.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct Thresholds {
+    /// Failed passes in a row (defaults to 3).
+    pub consecutive_failures: Option<u32>,
+    /// Share of failed passes among the last `window` ones, from 0 to 1. Unset by default.
+    pub failure_ratio: Option<f64>,
+    /// How many passes `failure_ratio` looks at (defaults to 10).
+    pub window: Option<usize>,
+}
+
+impl Thresholds {
+    fn window(&self) -> usize {
+        self.window.unwrap_or(10).max(1)
+    }
+}
+
+/// Outcomes of the most recent passes, `true` meaning complete, most recent last.
+static PASSES: Mutex<VecDeque<bool>> = Mutex::new(VecDeque::new());
+
+/// The most passes any threshold looks at.
+const MAX_PASSES: usize = 1000;
+
+pub fn record_pass(complete: bool) {
+    let mut passes = PASSES.lock().unwrap();
+    if passes.len() == MAX_PASSES {
+        passes.pop_front();
+    }
+    passes.push_back(complete);
+}
+
+#[derive(serde::Serialize)]
+struct Status {
+    healthy: bool,
+    consecutive_failures: usize,
+    recent_failures: usize,
+    recent_passes: usize,
+}
+
+fn status(thresholds: &Thresholds) -> Status {
+    let passes = PASSES.lock().unwrap();
+    let consecutive_failures = passes.iter().rev().take_while(|complete| !**complete).count();
+    let recent: Vec<_> = passes.iter().rev().take(thresholds.window()).collect();
+    let recent_failures = recent.iter().filter(|complete| !***complete).count();
+    let too_many_in_a_row = consecutive_failures >= thresholds.consecutive_failures.unwrap_or(3).max(1) as usize;
+    let too_many_overall = thresholds.failure_ratio
+        .is_some_and(|ratio| !recent.is_empty() && recent_failures as f64 / recent.len() as f64 >= ratio);
+    Status { healthy: !too_many_in_a_row && !too_many_overall, consecutive_failures, recent_failures, recent_passes: recent.len() }
+}
+
+/// Starts answering health checks on `listener`, with `history` for `/status`.
+pub fn serve(listener: TcpListener, thresholds: Thresholds, history: History) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = respond(stream, &thresholds, &history) {
+                        log::debug!("Failed to answer a health check: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("Failed to accept a health check connection: {}", e),
+            }
+        }
+    });
+}
+
+fn respond(mut stream: TcpStream, thresholds: &Thresholds, history: &History) -> std::io::Result<()> {
+    // A client that never sends its request shouldn't block the others.
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, content_type, body) = match path {
+        "/" | "/health" => {
+            let status = status(thresholds);
+            (if status.healthy { "200 OK" } else { "503 Service Unavailable" }, "application/json", serde_json::to_string(&status)?)
+        }
+        "/metrics" => ("200 OK", "application/openmetrics-text; version=1.0.0; charset=utf-8", crate::metrics::render()),
+        "/status" => ("200 OK", "application/json", history.to_json()?),
+        _ => ("404 Not Found", "application/json", "{}".to_string()),
+    };
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body)
+}
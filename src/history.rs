@@ -0,0 +1,97 @@
+use std::{collections::{BTreeMap, VecDeque}, sync::{Arc, Mutex}, time::SystemTime};
+
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "outcome", content = "error", rename_all = "lowercase")]
+pub enum Outcome {
+    Updated,
+    Failed(String),
+    /// Not written yet because of `min_update_interval_secs`.
+    Held,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub at: SystemTime,
+    pub outcome: Outcome,
+}
+
+/// Outcomes by zone and record name, oldest first.
+type Records = BTreeMap<(String, String), VecDeque<Entry>>;
+
+/// Keeps the last few update outcomes of every record, so sporadic failures
+/// show up as a pattern instead of single lines buried in the log.
+///
+/// Clones share the outcomes, so the `/status` endpoint sees what the update passes record.
+#[derive(Clone, Debug)]
+pub struct History {
+    capacity: usize,
+    records: Arc<Mutex<Records>>,
+}
+
+#[derive(serde::Serialize)]
+struct RecordStatus<'a> {
+    zone: &'a str,
+    record: &'a str,
+    /// Oldest first.
+    history: Vec<EntryStatus<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct EntryStatus<'a> {
+    at: String,
+    #[serde(flatten)]
+    outcome: &'a Outcome,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, records: Arc::default() }
+    }
+
+    pub fn record(&mut self, zone: &str, record: &str, outcome: Outcome) {
+        let mut records = self.records.lock().unwrap();
+        let entries = records.entry((zone.to_string(), record.to_string())).or_default();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        if self.capacity > 0 {
+            entries.push_back(Entry { at: SystemTime::now(), outcome });
+        }
+    }
+
+    /// Every record with its outcomes as JSON, for `GET /status`.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let records = self.records.lock().unwrap();
+        let status: Vec<_> = records.iter().map(|((zone, record), entries)| RecordStatus {
+            zone,
+            record,
+            history: entries.iter().map(|entry| EntryStatus { at: format_time(entry.at), outcome: &entry.outcome }).collect(),
+        }).collect();
+        serde_json::to_string(&status)
+    }
+
+    pub fn log_summary(&self) {
+        let records = self.records.lock().unwrap();
+        if records.is_empty() {
+            log::info!("No records have been updated yet");
+        }
+        for ((zone, record), entries) in records.iter() {
+            let failures = entries.iter().filter(|entry| matches!(entry.outcome, Outcome::Failed(_))).count();
+            log::info!("Record {} of zone {}: {} of the last {} updates failed", record, zone, failures, entries.len());
+            for entry in entries {
+                let at = format_time(entry.at);
+                match &entry.outcome {
+                    Outcome::Updated => log::info!("  {} updated", at),
+                    Outcome::Failed(e) => log::info!("  {} failed: {}", at, e),
+                    Outcome::Held => log::info!("  {} held back by its cooldown", at),
+                }
+            }
+        }
+    }
+}
+
+fn format_time(at: SystemTime) -> String {
+    OffsetDateTime::from(at).format(&Rfc3339).unwrap_or_default()
+}
@@ -0,0 +1,226 @@
+use std::{collections::VecDeque, io::{self, Read}, path::PathBuf, sync::{Condvar, Mutex}, time::{Duration, Instant}};
+
+use isahc::{config::Configurable, http::{Request, Response}, Body, HttpClient};
+
+use crate::error::DynsError;
+
+/// Counting semaphore, so the number of requests in flight stays bounded no matter
+/// how many threads share the client.
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self { available: Mutex::new(permits), released: Condvar::new() }
+    }
+
+    fn acquire(&self) -> Permit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// A request and the response it got, as stored in a cassette file.
+/// Request headers are left out so credentials never end up on disk.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Interaction {
+    method: String,
+    url: String,
+    request_body: String,
+    status: u16,
+    response_body: String,
+}
+
+enum Mode {
+    Live,
+    /// Performs requests and appends them to the cassette at `path`.
+    Record { path: PathBuf, interactions: Mutex<Vec<Interaction>> },
+    /// Answers requests from a cassette without touching the network.
+    Replay { interactions: Mutex<VecDeque<Interaction>> },
+}
+
+/// The longest any single request may take, so a server that never answers can't stall dyns.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The HTTP client every outbound request goes through.
+pub struct Http {
+    client: HttpClient,
+    permits: Semaphore,
+    mode: Mode,
+    /// Requests get no more time than is left until then, see [`Http::set_deadline`].
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl Http {
+    pub fn new(max_concurrent_requests: usize) -> Result<Self, DynsError> {
+        // Sends `Accept-Encoding` for every encoding curl can decode, which helps with large zone listings.
+        let client = HttpClient::builder().automatic_decompression(true).timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self { client, permits: Semaphore::new(max_concurrent_requests), mode: Mode::Live, deadline: Mutex::new(None) })
+    }
+
+    /// Makes requests time out at `deadline`, e.g. the end of an update pass, and fail right away
+    /// once it passed. `None` lifts it again.
+    pub fn set_deadline(&self, deadline: Option<Instant>) {
+        *self.deadline.lock().unwrap() = deadline;
+    }
+
+    /// Records every interaction into a cassette at `path`, overwriting it.
+    pub fn record_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.mode = Mode::Record { path: path.into(), interactions: Mutex::default() };
+        self
+    }
+
+    /// Serves every request from the cassette at `path` instead of the network.
+    pub fn replay_from(mut self, path: impl Into<PathBuf>) -> Result<Self, DynsError> {
+        let cassette = std::fs::read_to_string(path.into())?;
+        let interactions = serde_json::from_str(&cassette).map_err(io::Error::from)?;
+        self.mode = Mode::Replay { interactions: Mutex::new(interactions) };
+        Ok(self)
+    }
+
+    pub fn send(&self, request: Request<Body>) -> Result<Response<Body>, DynsError> {
+        let _permit = self.permits.acquire();
+        let method = request.method().clone();
+        let host = request.uri().host().unwrap_or_default().to_string();
+        let started = Instant::now();
+        let result = match *self.deadline.lock().unwrap() {
+            Some(deadline) if deadline <= started => Err(isahc::error::ErrorKind::Timeout.into()),
+            Some(deadline) => self.dispatch(with_timeout(request, (deadline - started).min(REQUEST_TIMEOUT))),
+            None => self.dispatch(request),
+        };
+        let elapsed = started.elapsed().as_millis();
+        match &result {
+            Ok(response) => log::debug!("{} {} -> {} in {}ms", method, host, response.status().as_u16(), elapsed),
+            Err(e) => log::debug!("{} {} failed after {}ms: {}", method, host, elapsed, e),
+        }
+        Ok(result?)
+    }
+
+    fn dispatch(&self, request: Request<Body>) -> Result<Response<Body>, isahc::Error> {
+        match &self.mode {
+            Mode::Live => self.client.send(request),
+            Mode::Record { path, interactions } => {
+                let (parts, mut body) = request.into_parts();
+                let request_body = read_to_string(&mut body)?;
+                let request = Request::from_parts(parts, Body::from(request_body.clone()));
+                let (method, url) = (request.method().to_string(), request.uri().to_string());
+
+                let (parts, mut body) = self.client.send(request)?.into_parts();
+                let response_body = read_to_string(&mut body)?;
+                let mut interactions = interactions.lock().unwrap();
+                interactions.push(Interaction { method, url, request_body, status: parts.status.as_u16(), response_body: response_body.clone() });
+                std::fs::write(path, serde_json::to_string_pretty(&*interactions).map_err(io::Error::from)?)?;
+                Ok(Response::from_parts(parts, Body::from(response_body)))
+            }
+            Mode::Replay { interactions } => {
+                let (method, url) = (request.method().to_string(), request.uri().to_string());
+                let mut interactions = interactions.lock().unwrap();
+                let position = interactions.iter().position(|i| i.method == method && i.url == url)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No recorded interaction for {} {}", method, url)))?;
+                let interaction = interactions.remove(position).unwrap();
+                Ok(Response::builder().status(interaction.status).body(Body::from(interaction.response_body))?)
+            }
+        }
+    }
+}
+
+/// `request` with `timeout` instead of the client's, keeping whatever else was configured on it.
+fn with_timeout(request: Request<Body>, timeout: Duration) -> Request<Body> {
+    let (mut parts, body) = request.into_parts();
+    let mut builder = Request::builder().method(parts.method).uri(parts.uri).version(parts.version);
+    if let Some(headers) = builder.headers_mut() {
+        *headers = parts.headers;
+    }
+    if let Some(extensions) = builder.extensions_mut() {
+        std::mem::swap(extensions, &mut parts.extensions);
+    }
+    builder.timeout(timeout).body(body).expect("a request that was already valid")
+}
+
+fn read_to_string(body: &mut Body) -> io::Result<String> {
+    let mut buf = String::new();
+    body.read_to_string(&mut buf)?;
+    Ok(buf)
+}
+
+/// How much of a body that failed to parse is kept for the error message.
+const HEAD_LEN: usize = 200;
+
+/// Parses a JSON body while it streams in rather than buffering all of it first. On failure, the
+/// error comes with the start of the body, for logging.
+pub fn parse_json<T: serde::de::DeserializeOwned>(body: impl Read) -> Result<T, (serde_json::Error, String)> {
+    let mut reader = io::BufReader::new(Head { inner: body, head: Vec::new() });
+    serde_json::from_reader(&mut reader).map_err(|e| (e, String::from_utf8_lossy(&reader.get_ref().head).into_owned()))
+}
+
+/// Remembers the first [`HEAD_LEN`] bytes read through it.
+struct Head<R> {
+    inner: R,
+    head: Vec<u8>,
+}
+
+impl<R: Read> Read for Head<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let keep = (HEAD_LEN - self.head.len()).min(read);
+        self.head.extend_from_slice(&buf[..keep]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader, Write}, net::TcpListener};
+
+    use super::*;
+
+    /// `{"answer":42}`, gzipped.
+    const GZIPPED: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xab, 0x56, 0x4a, 0xcc, 0x2b, 0x2e, 0x4f,
+        0x2d, 0x52, 0xb2, 0x32, 0x31, 0xaa, 0x05, 0x00, 0xf5, 0x65, 0xd9, 0xcc, 0x0d, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decodes_gzipped_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut accept_encoding = String::new();
+            for line in BufReader::new(&stream).lines() {
+                let line = line.unwrap();
+                if line.is_empty() {
+                    break;
+                }
+                if line.to_ascii_lowercase().starts_with("accept-encoding:") {
+                    accept_encoding = line;
+                }
+            }
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                   GZIPPED.len()).unwrap();
+            stream.write_all(GZIPPED).unwrap();
+            accept_encoding
+        });
+
+        let client = Http::new(1).unwrap();
+        let mut response = client.send(Request::get(url).body(Body::empty()).unwrap()).unwrap();
+        let body: serde_json::Value = parse_json(response.body_mut()).unwrap();
+        assert_eq!(body, serde_json::json!({ "answer": 42 }));
+        assert!(server.join().unwrap().contains("gzip"));
+    }
+}
@@ -0,0 +1,58 @@
+//! Looks at the host's network interfaces, for `wait_for_interface`.
+
+use std::{io, net::IpAddr};
+
+/// Whether `addr` is reachable beyond its own link: not loopback, link-local or unspecified.
+#[cfg(unix)]
+fn is_global(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => !addr.is_loopback() && !addr.is_link_local() && !addr.is_unspecified(),
+        IpAddr::V6(addr) => !addr.is_loopback() && !addr.is_unicast_link_local() && !addr.is_unspecified(),
+    }
+}
+
+/// The global addresses of `interface`, or `None` if there is no such interface.
+#[cfg(unix)]
+pub fn global_addresses(interface: &str) -> io::Result<Option<Vec<IpAddr>>> {
+    use std::{ffi::CStr, net::{Ipv4Addr, Ipv6Addr}};
+
+    let mut list = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut found = false;
+    let mut addresses = Vec::new();
+    let mut entry = list;
+    // Interfaces show up once per address, and on Linux once more without one.
+    while let Some(ifa) = unsafe { entry.as_ref() } {
+        entry = ifa.ifa_next;
+        if unsafe { CStr::from_ptr(ifa.ifa_name) }.to_bytes() != interface.as_bytes() {
+            continue;
+        }
+        found = true;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let addr = match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+        if is_global(addr) {
+            addresses.push(addr);
+        }
+    }
+    unsafe { libc::freeifaddrs(list) };
+    Ok(found.then_some(addresses))
+}
+
+#[cfg(not(unix))]
+pub fn global_addresses(_interface: &str) -> io::Result<Option<Vec<IpAddr>>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "listing interface addresses is only supported on Unix"))
+}
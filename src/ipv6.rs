@@ -0,0 +1,49 @@
+//! Tracks the host's own global IPv6 address, for ISPs that delegate a prefix which rotates.
+
+use std::{io, net::Ipv6Addr};
+
+/// A global address assigned to an interface, along with its prefix length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub addr: Ipv6Addr,
+    pub prefix_len: u8,
+}
+
+impl InterfaceAddress {
+    /// Keeps the network part of this address and replaces the host part with `suffix`.
+    pub fn with_suffix(&self, suffix: Ipv6Addr) -> Ipv6Addr {
+        let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+        Ipv6Addr::from((u128::from(self.addr) & mask) | (u128::from(suffix) & !mask))
+    }
+}
+
+/// Finds the first stable global address of `interface`, skipping temporary (privacy) and deprecated ones.
+/// Unique local addresses (fc00::/7) are skipped too: Linux reports them with global scope, but
+/// they aren't reachable from the internet.
+#[cfg(target_os = "linux")]
+pub fn global_address(interface: &str) -> io::Result<InterfaceAddress> {
+    const SCOPE_GLOBAL: u8 = 0x00;
+    const IFA_F_TEMPORARY: u8 = 0x01;
+    const IFA_F_DEPRECATED: u8 = 0x20;
+    // Each line reads "<address> <ifindex> <prefix len> <scope> <flags> <name>", numbers in hex.
+    for line in std::fs::read_to_string("/proc/net/if_inet6")?.lines() {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let [addr, _, prefix_len, scope, flags, name] = fields[..] else { continue };
+        let hex = |field| u8::from_str_radix(field, 16).ok();
+        let (Some(prefix_len), Some(scope), Some(flags)) = (hex(prefix_len), hex(scope), hex(flags)) else { continue };
+        if name != interface || scope != SCOPE_GLOBAL || flags & (IFA_F_TEMPORARY | IFA_F_DEPRECATED) != 0 {
+            continue;
+        }
+        match u128::from_str_radix(addr, 16).map(Ipv6Addr::from) {
+            Ok(addr) if addr.is_unique_local() => continue,
+            Ok(addr) => return Ok(InterfaceAddress { addr, prefix_len }),
+            Err(_) => continue,
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("{} has no global IPv6 address", interface)))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn global_address(_interface: &str) -> io::Result<InterfaceAddress> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reading interface addresses is only supported on Linux"))
+}
@@ -0,0 +1,87 @@
+//! A log file whose path may contain strftime-style date fields, reopened whenever the expanded
+//! path changes, e.g. at midnight for `/var/log/dyns-%Y-%m-%d.log`.
+
+use std::{fs::File, io::{self, Write}, path::{Path, PathBuf}};
+
+use crate::clock::{local_now, LocalTime};
+
+pub struct DatedFile {
+    template: String,
+    path: PathBuf,
+    file: File,
+}
+
+impl DatedFile {
+    /// Opens the file for the current date. Dated files are appended to, since a restart on the
+    /// same day shouldn't wipe the day's log; plain paths are truncated as they always were.
+    pub fn open(template: &Path) -> io::Result<Self> {
+        let template = template.to_string_lossy().into_owned();
+        let path = expand(&template, local_now());
+        let file = open(&template, &path)?;
+        Ok(Self { template, path, file })
+    }
+
+    /// Makes sure the file for the current date can be written to, without truncating it.
+    pub fn check_writable(template: &Path) -> io::Result<PathBuf> {
+        let path = expand(&template.to_string_lossy(), local_now());
+        std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(path)
+    }
+
+    pub fn is_dated(template: &Path) -> bool {
+        template.to_string_lossy().contains('%')
+    }
+}
+
+fn open(template: &str, path: &Path) -> io::Result<File> {
+    if DatedFile::is_dated(Path::new(template)) {
+        std::fs::OpenOptions::new().create(true).append(true).open(path)
+    } else {
+        File::create(path)
+    }
+}
+
+impl Write for DatedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if DatedFile::is_dated(Path::new(&self.template)) {
+            let path = expand(&self.template, local_now());
+            // Rather the old file than no log at all, e.g. when privileges were dropped since.
+            if path != self.path {
+                if let Ok(file) = open(&self.template, &path) {
+                    self.file = file;
+                    self.path = path;
+                }
+            }
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Replaces `%Y`, `%m`, `%d`, `%H` and `%%` in `template`; anything else is kept as is.
+fn expand(template: &str, now: LocalTime) -> PathBuf {
+    let mut path = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            path.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => path += &format!("{:04}", now.year),
+            Some('m') => path += &format!("{:02}", now.month),
+            Some('d') => path += &format!("{:02}", now.day),
+            Some('H') => path += &format!("{:02}", now.hour),
+            Some('%') => path.push('%'),
+            Some(other) => {
+                path.push('%');
+                path.push(other);
+            }
+            None => path.push('%'),
+        }
+    }
+    PathBuf::from(path)
+}
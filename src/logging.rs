@@ -0,0 +1,310 @@
+//! Declarative log outputs: each entry of `log_outputs` becomes one logger with its own target,
+//! level and format.
+
+use std::{fmt, io::{self, Write}, path::Path, sync::{Mutex, OnceLock}, time::Instant};
+
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::{ColorChoice, ConfigBuilder, SharedLogger, SimpleLogger, TermLogger, TerminalMode, WriteLogger};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::logfile::DatedFile;
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct LogOutput {
+    pub target: LogTarget,
+    /// File to write to, for the `file` target. May contain date fields like `log_file`.
+    pub path: Option<String>,
+    #[serde(default = "LogLevel::info")]
+    pub level: LogLevel,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Colorize text on the console, with timestamps relative to startup.
+    #[serde(default)]
+    pub color: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    /// Errors to stderr, everything else to stdout.
+    Console,
+    Stdout,
+    Stderr,
+    File,
+    /// The local syslog daemon, as facility `daemon`. Unix only, always plain text.
+    Syslog,
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn info() -> Self {
+        Self::Info
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+impl From<LevelFilter> for LogLevel {
+    fn from(level: LevelFilter) -> Self {
+        match level {
+            LevelFilter::Off | LevelFilter::Error => LogLevel::Error,
+            LevelFilter::Warn => LogLevel::Warn,
+            LevelFilter::Info => LogLevel::Info,
+            LevelFilter::Debug => LogLevel::Debug,
+            LevelFilter::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line with `time`, `level`, `target` and `message`.
+    Json,
+}
+
+impl fmt::Display for LogOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.target, &self.path) {
+            (LogTarget::File, Some(path)) => write!(f, "{}", path)?,
+            (target, _) => write!(f, "{:?}", target)?,
+        }
+        write!(f, "({:?})", self.level)
+    }
+}
+
+/// isahc warns about every failed request on its own, which floods the log while offline;
+/// `http` already logs each request along with its outcome.
+const IGNORED_TARGET: &str = "isahc";
+
+fn text_config() -> ConfigBuilder {
+    let mut builder = ConfigBuilder::new();
+    builder.add_filter_ignore_str(IGNORED_TARGET);
+    builder
+}
+
+fn ignored(metadata: &Metadata) -> bool {
+    metadata.target().starts_with(IGNORED_TARGET)
+}
+
+impl LogOutput {
+    /// Builds the logger for this output.
+    pub fn build(&self) -> io::Result<Box<dyn SharedLogger>> {
+        let level = self.level.into();
+        let mode = match self.target {
+            LogTarget::Console => TerminalMode::Mixed,
+            LogTarget::Stdout => TerminalMode::Stdout,
+            LogTarget::Stderr => TerminalMode::Stderr,
+            LogTarget::File => {
+                let path = self.path.as_deref().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "file log output without a path"))?;
+                let file = DatedFile::open(Path::new(path))?;
+                return Ok(match self.format {
+                    LogFormat::Text => WriteLogger::new(level, text_config().build(), file),
+                    LogFormat::Json => JsonLogger::boxed(level, file),
+                });
+            }
+            LogTarget::Syslog => return SyslogLogger::open(level),
+        };
+        Ok(match (self.format, self.color) {
+            (LogFormat::Json, _) if self.target == LogTarget::Stderr => JsonLogger::boxed(level, io::stderr()),
+            (LogFormat::Json, _) => JsonLogger::boxed(level, io::stdout()),
+            (LogFormat::Text, true) => ColorLogger::boxed(level, mode),
+            (LogFormat::Text, false) if self.target == LogTarget::Console => SimpleLogger::new(level, text_config().build()),
+            (LogFormat::Text, false) => TermLogger::new(level, text_config().build(), mode, ColorChoice::Never),
+        })
+    }
+}
+
+struct JsonLogger<W> {
+    level: LevelFilter,
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send + 'static> JsonLogger<W> {
+    fn boxed(level: LevelFilter, writer: W) -> Box<dyn SharedLogger> {
+        Box::new(Self { level, writer: Mutex::new(writer) })
+    }
+}
+
+impl<W: Write + Send> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level && !ignored(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = serde_json::json!({
+            "time": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        });
+        let _ = writeln!(self.writer.lock().unwrap(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = self.writer.lock().unwrap().flush();
+    }
+}
+
+impl<W: Write + Send + 'static> SharedLogger for JsonLogger<W> {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        self
+    }
+}
+
+/// Colored levels and the time since startup, for people watching a terminal.
+struct ColorLogger {
+    level: LevelFilter,
+    mode: TerminalMode,
+}
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+impl ColorLogger {
+    fn boxed(level: LevelFilter, mode: TerminalMode) -> Box<dyn SharedLogger> {
+        STARTED.get_or_init(Instant::now);
+        Box::new(Self { level, mode })
+    }
+}
+
+impl Log for ColorLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level && !ignored(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let color = match record.level() {
+            log::Level::Error => "31",
+            log::Level::Warn => "33",
+            log::Level::Info => "32",
+            log::Level::Debug => "34",
+            log::Level::Trace => "35",
+        };
+        let elapsed = STARTED.get_or_init(Instant::now).elapsed().as_secs_f64();
+        let line = format!("\x1b[2m+{:.3}s\x1b[0m \x1b[{}m{:<5}\x1b[0m {}", elapsed, color, record.level(), record.args());
+        let to_stderr = match self.mode {
+            TerminalMode::Stderr => true,
+            TerminalMode::Mixed => record.level() == log::Level::Error,
+            TerminalMode::Stdout => false,
+        };
+        if to_stderr {
+            let _ = writeln!(io::stderr(), "{}", line);
+        } else {
+            let _ = writeln!(io::stdout(), "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+    }
+}
+
+impl SharedLogger for ColorLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        self
+    }
+}
+
+struct SyslogLogger {
+    level: LevelFilter,
+}
+
+impl SyslogLogger {
+    #[cfg(unix)]
+    fn open(level: LevelFilter) -> io::Result<Box<dyn SharedLogger>> {
+        // openlog keeps the pointer, hence the static.
+        static IDENT: &std::ffi::CStr = c"dyns";
+        unsafe { libc::openlog(IDENT.as_ptr(), libc::LOG_PID, libc::LOG_DAEMON) };
+        Ok(Box::new(Self { level }))
+    }
+
+    #[cfg(not(unix))]
+    fn open(_level: LevelFilter) -> io::Result<Box<dyn SharedLogger>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "syslog is only supported on Unix"))
+    }
+}
+
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level && !ignored(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        #[cfg(unix)]
+        {
+            let priority = match record.level() {
+                log::Level::Error => libc::LOG_ERR,
+                log::Level::Warn => libc::LOG_WARNING,
+                log::Level::Info => libc::LOG_INFO,
+                log::Level::Debug | log::Level::Trace => libc::LOG_DEBUG,
+            };
+            let message = record.args().to_string().replace('\0', "");
+            if let Ok(message) = std::ffi::CString::new(message) {
+                unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for SyslogLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        self
+    }
+}
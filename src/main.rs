@@ -1,30 +1,681 @@
-use std::{time::Duration, path::Path};
+use std::{time::{Duration, Instant, SystemTime}, path::{Path, PathBuf}, collections::{BTreeMap, HashMap}, io::IsTerminal, net::{IpAddr, Ipv6Addr, SocketAddr}};
 
 use anyhow::Context;
-use isahc::{Request, Body, ReadResponseExt};
+use isahc::{Request, Body, ReadResponseExt, config::{Configurable, IpVersion}, http::header::{HeaderName, HeaderValue}};
 
-static CONFIG_FILE: &str = "/etc/dyns.toml";
-static LOG_FILE: &str = "/var/log/dyns.log";
+mod cidr;
+mod clock;
+mod cooldown;
+mod dedup;
+#[cfg(unix)]
+mod daemon;
+mod dns;
+mod error;
+mod health;
+mod history;
+mod http;
+mod interface;
+mod ipv6;
+mod logfile;
+mod logging;
+mod metrics;
+mod paths;
+mod provider;
+mod signals;
+#[cfg(unix)]
+mod socket;
+mod state;
+mod watch;
+mod window;
+
+use cidr::Cidr;
+use error::DynsError;
+use history::{History, Outcome};
+use http::Http;
+use logging::{LogFormat, LogLevel, LogOutput, LogTarget};
+use ipv6::InterfaceAddress;
+use logfile::DatedFile;
+use state::State;
+use provider::{DnsProvider, Rfc2136Config, Rfc2136Provider};
+use watch::FileWatcher;
+use window::UpdateWindow;
+
+#[cfg(unix)]
+static PID_FILE: &str = "/run/dyns.pid";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum RecordType {
+    #[default]
+    A,
+    Aaaa,
+    Cname,
+    Txt,
+    Mx,
+    Srv,
+    Ns,
+    Caa,
+}
+
+impl RecordType {
+    /// Cloudflare only allows proxying these record types.
+    fn is_proxiable(self) -> bool {
+        matches!(self, RecordType::A | RecordType::Aaaa | RecordType::Cname)
+    }
+}
+
+impl std::fmt::Display for RecordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RecordType::A => "A",
+            RecordType::Aaaa => "AAAA",
+            RecordType::Cname => "CNAME",
+            RecordType::Txt => "TXT",
+            RecordType::Mx => "MX",
+            RecordType::Srv => "SRV",
+            RecordType::Ns => "NS",
+            RecordType::Caa => "CAA",
+        };
+        f.write_str(name)
+    }
+}
 
 #[derive(Clone, Debug, serde::Deserialize)]
 struct Record {
     name: String,
-    proxy: bool
+    /// Falls back to the zone's `default_type`, then to A.
+    #[serde(rename = "type")]
+    type_: Option<RecordType>,
+    /// Falls back to the zone's `default_proxy`, then to false.
+    proxy: Option<bool>,
+    /// Falls back to the zone's `default_ttl`, then to whatever the record already has.
+    ttl: Option<u32>,
+    /// Structured data for SRV records.
+    data: Option<SrvData>,
+    /// Records with a lower order are updated first, e.g. an A record before a CNAME pointing at it.
+    #[serde(default)]
+    order: i32,
+    /// Manage one record under this name per value instead of a single record: missing ones are
+    /// created, changed ones updated and extra ones deleted. `${ip}` is replaced by the current IP.
+    contents: Option<Vec<String>>,
+    /// Like `contents`, but the values are the lines printed by this shell command.
+    contents_command: Option<String>,
+    /// For AAAA records with `ipv6_interface` set: the host part, combined with the interface's current prefix.
+    ipv6_suffix: Option<Ipv6Addr>,
+    /// Content to publish instead of the bare address. `{ip}` is replaced by the address, `{ip_reversed}` by
+    /// its labels in reverse order (4.3.2.1 for 1.2.3.4) and `{ip_dashed}` by it with dashes instead of dots or colons.
+    content_template: Option<String>,
+    /// Cloudflare comment of the record, enforced when `sync_fields` includes "comment".
+    comment: Option<String>,
+    /// Cloudflare tags of the record, enforced when `sync_fields` includes "tags".
+    #[serde(default)]
+    cloudflare_tags: Vec<String>,
+    /// Create the record when no record has its name and type yet, instead of failing. Falls back to the zone's
+    /// `default_create_missing`, then to false.
+    create_missing: Option<bool>,
+    /// Don't write this record again sooner than this after it was last written. Falls back to the top-level
+    /// `min_update_interval_secs`.
+    min_update_interval_secs: Option<u64>,
+    /// Detect this record's address over each of these uplinks instead of using the global one, for multi-WAN setups.
+    #[serde(default)]
+    wan_sources: Vec<WanSource>,
+    /// With `wan_sources`: "failover" publishes the first uplink that answers, "all" one record per uplink.
+    #[serde(default)]
+    wan_policy: WanPolicy,
+    /// Labels for selecting records with --only-tags. Only used by dyns, unlike `cloudflare_tags`.
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl Record {
+    fn record_type(&self) -> RecordType {
+        self.type_.unwrap_or_default()
+    }
+
+    fn proxied(&self) -> bool {
+        self.proxy.unwrap_or(false)
+    }
+
+    fn creates_missing(&self) -> bool {
+        self.create_missing.unwrap_or(false)
+    }
+
+    /// The address this record should point to, if it's known. A and AAAA records only ever get an
+    /// address of their own family; AAAA records prefer the IPv6 address detected on its own.
+    fn address(&self, addresses: Addresses) -> Option<String> {
+        let ipv6 = addresses.ipv6.or(match addresses.ip {
+            Some(IpAddr::V6(addr)) => Some(InterfaceAddress { addr, prefix_len: 128 }),
+            _ => None,
+        });
+        match (self.record_type(), ipv6, self.ipv6_suffix) {
+            (RecordType::Aaaa, Some(ipv6), Some(suffix)) => Some(ipv6.with_suffix(suffix).to_string()),
+            (RecordType::Aaaa, Some(ipv6), None) => Some(ipv6.addr.to_string()),
+            (RecordType::Aaaa, None, _) => None,
+            (RecordType::A, _, _) => addresses.ip.filter(IpAddr::is_ipv4).map(|ip| ip.to_string()),
+            _ => addresses.primary().map(|ip| ip.to_string()),
+        }
+    }
+
+    /// This record as published with `wan` and its address, or `None` if no address is known. With
+    /// `wan_policy = "all"` that's a copy managing one record per uplink that answered.
+    fn resolve(&self, zone: &str, addresses: Addresses, wan: &WanAddresses) -> Option<(std::borrow::Cow<'_, Record>, String)> {
+        let Some(found) = wan.get(&(zone.to_string(), self.name.clone(), self.record_type())) else {
+            return self.address(addresses).map(|address| (std::borrow::Cow::Borrowed(self), address));
+        };
+        let first = found.first()?.to_string();
+        if self.wan_policy == WanPolicy::Failover {
+            return Some((std::borrow::Cow::Borrowed(self), first));
+        }
+        let contents = found.iter().map(|ip| self.content(&ip.to_string())).collect();
+        let record = Record { contents: Some(contents), contents_command: None, content_template: None, ..self.clone() };
+        Some((std::borrow::Cow::Owned(record), first))
+    }
+
+    /// What gets published for `address`, after applying `content_template`.
+    fn content(&self, address: &str) -> String {
+        let Some(template) = &self.content_template else { return address.to_string() };
+        let reversed = match address.parse() {
+            Ok(IpAddr::V6(ip)) => ip.octets().iter().rev().map(|byte| format!("{:x}.{:x}", byte & 0xf, byte >> 4)).collect::<Vec<_>>().join("."),
+            _ => address.rsplit('.').collect::<Vec<_>>().join("."),
+        };
+        template.replace("{ip_reversed}", &reversed)
+            .replace("{ip_dashed}", &address.replace(['.', ':'], "-"))
+            .replace("{ip}", address)
+    }
+
+    /// The values a record set should hold, or `None` for a plain single record.
+    fn desired_contents(&self, ip: &str) -> Result<Option<Vec<String>>, DynsError> {
+        let mut contents: Vec<String> = match (&self.contents, &self.contents_command) {
+            (Some(contents), _) => contents.iter().map(|content| content.replace("${ip}", ip)).collect(),
+            (None, Some(command)) => {
+                let failed = |reason: String| DynsError::Command { what: format!("contents_command of {}", self.name), reason };
+                let output = std::process::Command::new("sh").arg("-c").arg(command).output().map_err(|e| failed(e.to_string()))?;
+                if !output.status.success() {
+                    return Err(failed(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+                }
+                String::from_utf8(output.stdout).map_err(|_| failed("its output isn't UTF-8".to_string()))?.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            (None, None) => return Ok(None),
+        };
+        let mut seen = std::collections::HashSet::new();
+        contents.retain(|content| seen.insert(content.clone()));
+        if contents.is_empty() {
+            return Err(DynsError::Record(format!("Record {} has no contents, refusing to delete all of its records", self.name)));
+        }
+        Ok(Some(contents))
+    }
+}
+
+/// An uplink the address of a record is detected through, see `wan_sources`.
+#[derive(Clone, Debug, serde::Deserialize)]
+struct WanSource {
+    /// Interface the detection request goes out of, e.g. the second uplink's. Defaults to the routing table's choice.
+    interface: Option<String>,
+    /// Service asked for the address (defaults to the first of `ip_providers`).
+    url: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WanPolicy {
+    #[default]
+    Failover,
+    All,
+}
+
+/// Addresses found through `wan_sources` by zone, record name and type, in the order of the sources.
+/// The type keeps the A and AAAA records of a dual-stack name apart.
+type WanAddresses = BTreeMap<(String, String, RecordType), Vec<IpAddr>>;
+
+/// `target` may contain `${ip}`, which is replaced by the current IP address.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+struct SrvData {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 struct Config {
-    email: String,
-    auth_key: String,
-    authorization: String,
+    /// Legacy authentication, together with `auth_key`.
+    email: Option<String>,
+    auth_key: Option<String>,
+    /// API token, preferred over `email` and `auth_key` when both are set.
+    authorization: Option<String>,
+    /// Where errors are logged. `%Y`, `%m`, `%d` and `%H` are replaced by the current date, starting a new file when it changes.
     log_file: Option<String>,
-    zones: Vec<ZoneConfig>
+    /// Replaces the console and `log_file` outputs, each entry with its own `target` (console, stdout, stderr, file or
+    /// syslog), `level` and `format` (text or json). --quiet, --verbose, --log-file and --no-log-file don't apply then.
+    log_outputs: Option<Vec<LogOutput>>,
+    /// Where dyns keeps what it last published, for `dyns stats` (defaults to /var/lib/dyns/state.json, or %PROGRAMDATA%\dyns\state.json on Windows; empty disables it).
+    state_file: Option<String>,
+    /// Exit cleanly after running this long so a supervisor can restart us fresh.
+    max_runtime_secs: Option<u64>,
+    /// Upper bound for a whole update pass, after which it is aborted until the next cycle.
+    pass_timeout_secs: Option<u64>,
+    /// Don't write any record again sooner than this after it was last written, a safety valve against
+    /// write storms. Records may override it. Updates held back are retried on the next poll, without
+    /// rewriting the records that already got theirs.
+    min_update_interval_secs: Option<u64>,
+    /// How many update outcomes to remember per record (defaults to 10).
+    history_size: Option<usize>,
+    /// Turn suspicious situations, like several records of one name and type, into errors instead of warnings.
+    #[serde(default)]
+    strict: bool,
+    /// Re-read each record right before writing it and start over if it changed since it was looked up,
+    /// so edits made meanwhile by other tools aren't silently clobbered. Costs an extra request per write.
+    #[serde(default)]
+    conditional_updates: bool,
+    /// Check every record before touching any: if one can't be updated, e.g. because it doesn't exist or
+    /// has no address yet, the whole pass is skipped instead of leaving the zones half-updated.
+    #[serde(default)]
+    transactional: bool,
+    /// Services asked for our public IP, in order of preference.
+    #[serde(default = "default_ip_providers")]
+    ip_providers: Vec<IpProvider>,
+    /// Whether `ip_providers` are asked at all (defaults to true). Turn off on IPv6-only hosts, A records are then left alone.
+    enable_ipv4: Option<bool>,
+    /// Also detect our public IPv6 address with `ipv6_providers`, and point AAAA records at it.
+    #[serde(default)]
+    enable_ipv6: bool,
+    /// Services asked for our public IPv6 address when `enable_ipv6` is set.
+    #[serde(default = "default_ipv6_providers")]
+    ipv6_providers: Vec<IpProvider>,
+    /// First delay between IP detection retries, doubled after every failure (defaults to 5).
+    ip_retry_base_secs: Option<u64>,
+    /// Cap for the delay between IP detection retries (defaults to 300).
+    ip_retry_max_secs: Option<u64>,
+    /// How often to check whether the network is back while no IP provider can even be reached (defaults to 15).
+    /// Unlike other detection failures this doesn't back off, so updates resume soon after the link comes up.
+    offline_poll_secs: Option<u64>,
+    /// How often the public IP is checked (defaults to 300).
+    poll_interval_secs: Option<u64>,
+    /// Wait this long before the first IP detection, e.g. for the network to come up at boot.
+    initial_delay_secs: Option<u64>,
+    /// Before the first update, wait until this interface exists and has a global address.
+    wait_for_interface: Option<String>,
+    /// How long to wait for `wait_for_interface` before going ahead anyway (defaults to 120).
+    wait_for_interface_timeout_secs: Option<u64>,
+    /// Upper bound for HTTP requests in flight at once (defaults to 4).
+    max_concurrent_requests: Option<usize>,
+    /// When set, IP changes are only published while the window is open.
+    update_window: Option<UpdateWindow>,
+    /// Detected addresses outside all of these networks are never published.
+    /// Empty means any address is accepted.
+    #[serde(default)]
+    allowed_cidrs: Vec<Cidr>,
+    /// Records of other types are skipped with a warning, whatever the zones say.
+    /// Empty means every type may be managed.
+    #[serde(default)]
+    allowed_record_types: Vec<RecordType>,
+    /// The records dropped for `allowed_record_types`, to warn about once logging is up.
+    #[serde(skip)]
+    disallowed_records: Vec<String>,
+    /// What to do with a detected address in the carrier-grade NAT range 100.64.0.0/10, which can't be
+    /// reached from the internet: "warn" (the default) and publish anyway, or "refuse" to publish it.
+    #[serde(default)]
+    on_cgnat: CgnatPolicy,
+    /// Point AAAA records at this interface's global IPv6 address instead of the detected IP, so they
+    /// follow a delegated prefix when the ISP rotates it. Linux only.
+    ipv6_interface: Option<String>,
+    /// Whether records are patched (the default) or fully replaced, dropping fields dyns doesn't manage.
+    #[serde(default)]
+    update_method: UpdateMethod,
+    /// Fields sent besides the content when patching (defaults to ["proxied", "ttl"]). Unset fields are
+    /// enforced too, except `ttl`: `proxied` falls back to false and a synced comment without `comment` in
+    /// the config is cleared, but a record without `ttl` keeps whatever TTL it has. PUT always sends everything.
+    sync_fields: Option<Vec<SyncField>>,
+    /// Only update records while this file exists, e.g. a lock held by the primary of two failover nodes.
+    /// IP detection and logging carry on either way.
+    active_when_file_exists: Option<String>,
+    /// Check that updated records actually resolve to the new address.
+    verify_propagation: Option<VerifyPropagation>,
+    /// Receives a JSON POST for every ip_changed and records_updated event.
+    webhook_url: Option<String>,
+    /// Drop root privileges after opening the config and log files. Note that a watched
+    /// config must stay readable by this user to be reloaded.
+    #[cfg(unix)]
+    run_as: Option<daemon::RunAs>,
+    /// Unix socket that streams every event as a line of JSON to whoever connects. Read at startup only.
+    #[cfg(unix)]
+    event_socket: Option<String>,
+    /// Address like 127.0.0.1:8080 to answer `GET /health` on: 200 while healthy, 503 once update passes
+    /// failed as often as `health_thresholds` allow. `GET /metrics` serves OpenMetrics there, and `GET /status`
+    /// the last `history_size` outcomes of every record as JSON. Read at startup only.
+    health_listen: Option<String>,
+    #[serde(default)]
+    health_thresholds: health::Thresholds,
+    /// Reload the config whenever the file changes on disk.
+    #[serde(default)]
+    watch_config: bool,
+    /// Directory of `*.toml` files holding one zone each (defaults to /etc/dyns.zones.d, or %PROGRAMDATA%\dyns\zones.d on Windows, if it exists).
+    zones_dir: Option<String>,
+    /// Zones that are always processed, whatever the profile.
+    #[serde(default)]
+    zones: Vec<ZoneConfig>,
+    /// Named sets of zones that can be selected with `--profile`.
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    /// Profile used when `--profile` isn't given. Without one, the zones of all profiles are processed.
+    default_profile: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct Profile {
+    #[serde(default)]
+    zones: Vec<ZoneConfig>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct VerifyPropagation {
+    /// Resolver to ask, e.g. `1.1.1.1` or `[2606:4700:4700::1111]:53`.
+    resolver: String,
+    /// How long to wait for the new address to show up (defaults to 60).
+    timeout_secs: Option<u64>,
+}
+
+impl VerifyPropagation {
+    fn resolver(&self) -> Result<SocketAddr, DynsError> {
+        self.resolver.parse()
+            .or_else(|_| self.resolver.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+            .map_err(|_| DynsError::Config(format!("Invalid resolver address {:?}", self.resolver)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum UpdateMethod {
+    #[default]
+    Patch,
+    Put,
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CgnatPolicy {
+    #[default]
+    Warn,
+    Refuse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SyncField {
+    Proxied,
+    Ttl,
+    Comment,
+    Tags,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct IpProvider {
+    url: String,
+    /// Extra headers, e.g. an API key or an `Accept` header asking for plain text.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Shortest polling interval the provider tolerates before throttling us.
+    /// Defaults to a known value for popular providers.
+    min_interval_secs: Option<u64>,
+}
+
+/// Rough rate limits of popular free IP services.
+const KNOWN_PROVIDER_INTERVALS: &[(&str, u64)] = &[
+    ("api.ipify.org", 60),
+    ("api4.ipify.org", 60),
+    ("api6.ipify.org", 60),
+    ("api64.ipify.org", 60),
+    ("icanhazip.com", 60),
+    ("ifconfig.me", 60),
+];
+
+impl IpProvider {
+    fn min_interval_secs(&self) -> Option<u64> {
+        self.min_interval_secs.or_else(|| {
+            let uri: isahc::http::Uri = self.url.parse().ok()?;
+            let host = uri.host()?;
+            KNOWN_PROVIDER_INTERVALS.iter().find(|(known, _)| *known == host).map(|(_, secs)| *secs)
+        })
+    }
+}
+
+fn default_ip_providers() -> Vec<IpProvider> {
+    vec![IpProvider { url: "https://api4.ipify.org/".into(), headers: BTreeMap::new(), min_interval_secs: None }]
+}
+
+fn default_ipv6_providers() -> Vec<IpProvider> {
+    vec![IpProvider { url: "https://api6.ipify.org/".into(), headers: BTreeMap::new(), min_interval_secs: None }]
+}
+
+impl ZoneConfig {
+    /// Makes every record inherit the zone defaults it doesn't override.
+    fn apply_defaults(&mut self) {
+        for record in &mut self.records {
+            record.proxy = record.proxy.or(self.default_proxy);
+            record.ttl = record.ttl.or(self.default_ttl);
+            record.type_ = record.type_.or(self.default_type);
+            record.create_missing = record.create_missing.or(self.default_create_missing);
+        }
+    }
+}
+
+impl Config {
+    fn ip_allowed(&self, ip: IpAddr) -> bool {
+        self.allowed_cidrs.is_empty() || self.allowed_cidrs.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    fn state_path(&self) -> Option<&Path> {
+        Some(Path::new(self.state_file.as_deref().unwrap_or(paths::state_file()))).filter(|path| !path.as_os_str().is_empty())
+    }
+
+    /// Which address family `ip_providers` must answer with: IPv4 as soon as there are A records to fill.
+    fn ip_version(&self) -> IpVersion {
+        match self.zones.iter().flat_map(|zone| &zone.records).any(|record| record.record_type() == RecordType::A) {
+            true => IpVersion::V4,
+            false => IpVersion::Any,
+        }
+    }
+
+    fn syncs(&self, field: SyncField) -> bool {
+        match &self.sync_fields {
+            Some(fields) => fields.contains(&field),
+            None => matches!(field, SyncField::Proxied | SyncField::Ttl),
+        }
+    }
+
+    /// Whether this instance should update records right now, see `active_when_file_exists`.
+    fn is_active(&self) -> bool {
+        self.active_when_file_exists.as_ref().is_none_or(|path| Path::new(path).exists())
+    }
+
+    fn ipv4_enabled(&self) -> bool {
+        self.enable_ipv4.unwrap_or(true)
+    }
+
+    fn ipv6_enabled(&self) -> bool {
+        self.enable_ipv6 || self.ipv6_interface.is_some()
+    }
+
+    /// All records of all zones, lowest `order` first and in config order otherwise.
+    fn ordered_records(&self) -> Vec<(&ZoneConfig, &Record)> {
+        let mut records: Vec<_> = self.zones.iter()
+            .flat_map(|zone| zone.records.iter().map(move |record| (zone, record)))
+            .collect();
+        records.sort_by_key(|(_, record)| record.order);
+        records
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs.unwrap_or(5*60))
+    }
+
+    /// Logs settings that are valid but likely to cause trouble.
+    fn warn_about_settings(&self) {
+        for record in &self.disallowed_records {
+            log::warn!("Skipping {}, its type is not in allowed_record_types", record);
+        }
+        let interval = self.poll_interval().as_secs();
+        let ipv4 = self.ip_providers.iter().filter(|_| self.ipv4_enabled());
+        let ipv6 = self.ipv6_providers.iter().filter(|_| self.enable_ipv6 && self.ipv6_interface.is_none());
+        for provider in ipv4.chain(ipv6) {
+            if let Some(min) = provider.min_interval_secs().filter(|min| interval < *min) {
+                log::warn!("poll_interval_secs is {}s, but {} may throttle clients polling more often than every {}s", interval, provider.url, min);
+            }
+        }
+    }
+
+    /// Logs one line of what is about to happen, so operators can confirm the service came up as intended.
+    fn log_startup_summary(&self, log_outputs: &[LogOutput]) {
+        let records: usize = self.zones.iter().map(|zone| zone.records.len()).sum();
+        let auth = match (&self.authorization, &self.email) {
+            (Some(_), _) => "token",
+            (None, Some(_)) => "key",
+            (None, None) => "none",
+        };
+        let hosts = |providers: &[IpProvider]| providers.iter()
+            .map(|provider| provider.url.parse::<isahc::http::Uri>().ok().and_then(|uri| uri.host().map(str::to_string)).unwrap_or_else(|| provider.url.clone()))
+            .collect::<Vec<_>>()
+            .join(",");
+        let ipv4 = if self.ipv4_enabled() { hosts(&self.ip_providers) } else { "off".to_string() };
+        let ipv6 = match (&self.ipv6_interface, self.enable_ipv6) {
+            (Some(interface), _) => format!("interface:{}", interface),
+            (None, true) => hosts(&self.ipv6_providers),
+            (None, false) => "off".to_string(),
+        };
+        let log = log_outputs.iter().map(ToString::to_string).collect::<Vec<_>>().join(",");
+        log::info!("Starting: zones={} records={} poll_interval={}s auth={} ipv4={} ipv6={} log={}",
+                   self.zones.len(), records, self.poll_interval().as_secs(), auth, ipv4, ipv6, log);
+    }
+
+    /// Resolves the relative paths of the config against `base`.
+    fn absolutize_paths(&mut self, base: &Path) {
+        let paths = [&mut self.log_file, &mut self.state_file, &mut self.active_when_file_exists, &mut self.zones_dir];
+        let outputs = self.log_outputs.iter_mut().flatten().map(|output| &mut output.path);
+        for path in paths.into_iter().chain(outputs).flatten() {
+            absolutize(base, path);
+        }
+        #[cfg(unix)]
+        if let Some(path) = &mut self.event_socket {
+            absolutize(base, path);
+        }
+    }
+
+    /// Adds the credentials of whichever authentication mode is configured.
+    fn authorize(&self, request: isahc::http::request::Builder) -> isahc::http::request::Builder {
+        match (&self.authorization, &self.email, &self.auth_key) {
+            (Some(token), _, _) => request.header("Authorization", format!("Bearer {}", token)),
+            (None, Some(email), Some(auth_key)) => request.header("X-Auth-Email", email).header("X-Auth-Key", auth_key),
+            // validate() makes sure this can't happen
+            _ => request,
+        }
+    }
+
+    fn runtime_exceeded(&self, started: Instant) -> bool {
+        match self.max_runtime_secs {
+            Some(max) => started.elapsed() >= Duration::from_secs(max),
+            None => false,
+        }
+    }
+
+    /// Catches mistakes that would otherwise only surface as API errors mid-loop.
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.zones.is_empty() {
+            anyhow::bail!("No zones specified");
+        }
+        if self.poll_interval_secs == Some(0) {
+            anyhow::bail!("poll_interval_secs must be at least 1");
+        }
+        if let Some(verify) = &self.verify_propagation {
+            verify.resolver()?;
+        }
+        for output in self.log_outputs.iter().flatten() {
+            if (output.target == LogTarget::File) != output.path.is_some() {
+                anyhow::bail!("Log outputs need a `path` exactly when their target is \"file\"");
+            }
+        }
+        if self.health_thresholds.failure_ratio.is_some_and(|ratio| !(0.0..=1.0).contains(&ratio)) {
+            anyhow::bail!("health_thresholds.failure_ratio must be between 0 and 1");
+        }
+        if self.offline_poll_secs == Some(0) {
+            anyhow::bail!("offline_poll_secs must be at least 1");
+        }
+        if self.max_concurrent_requests == Some(0) {
+            anyhow::bail!("max_concurrent_requests must be at least 1");
+        }
+        if !self.ipv4_enabled() && !self.ipv6_enabled() {
+            anyhow::bail!("enable_ipv4 is off and neither enable_ipv6 nor ipv6_interface is set, so there is no address to publish");
+        }
+        if self.ipv4_enabled() && self.ip_providers.is_empty() {
+            anyhow::bail!("No IP providers specified");
+        }
+        if self.enable_ipv6 && self.ipv6_interface.is_none() && self.ipv6_providers.is_empty() {
+            anyhow::bail!("enable_ipv6 is set but no ipv6_providers are specified");
+        }
+        for provider in self.ip_providers.iter().chain(&self.ipv6_providers) {
+            for (name, value) in &provider.headers {
+                HeaderName::from_bytes(name.as_bytes()).with_context(|| format!("Invalid header name {:?} for IP provider {}", name, provider.url))?;
+                HeaderValue::from_str(value).with_context(|| format!("Invalid value for header {} of IP provider {}", name, provider.url))?;
+            }
+        }
+        let uses_cloudflare = self.zones.iter().any(|zone| zone.rfc2136.is_none());
+        match (&self.authorization, &self.email, &self.auth_key) {
+            _ if !uses_cloudflare => (),
+            (Some(_), _, _) | (None, Some(_), Some(_)) => (),
+            (None, Some(_), None) => anyhow::bail!("`email` is set but `auth_key` is missing; legacy authentication needs both (or use `authorization` with an API token)"),
+            (None, None, Some(_)) => anyhow::bail!("`auth_key` is set but `email` is missing; legacy authentication needs both (or use `authorization` with an API token)"),
+            (None, None, None) => anyhow::bail!("No credentials specified: set `authorization` to an API token, or both `email` and `auth_key` (or pass --token, --email and --auth-key)"),
+        }
+        for zone in &self.zones {
+            for record in &zone.records {
+                if record.proxied() && zone.rfc2136.is_some() {
+                    anyhow::bail!("Record {} of zone {} has proxy = true, but proxying is a Cloudflare feature", record.name, zone.name);
+                }
+                if record.proxied() && !record.record_type().is_proxiable() {
+                    anyhow::bail!("Record {} of zone {} has proxy = true, but Cloudflare can only proxy A, AAAA and CNAME records (got {})",
+                                  record.name, zone.name, record.record_type());
+                }
+                if record.contents.is_some() && record.contents_command.is_some() {
+                    anyhow::bail!("Record {} of zone {} has both `contents` and `contents_command`", record.name, zone.name);
+                }
+                if record.data.is_some() && (record.contents.is_some() || record.contents_command.is_some()) {
+                    anyhow::bail!("SRV record {} of zone {} can't use `contents`", record.name, zone.name);
+                }
+                if record.content_template.is_some() && (record.data.is_some() || record.contents.is_some() || record.contents_command.is_some()) {
+                    anyhow::bail!("Record {} of zone {} can't combine content_template with `data`, `contents` or `contents_command`", record.name, zone.name);
+                }
+                if record.ipv6_suffix.is_some() && (record.record_type() != RecordType::Aaaa || self.ipv6_interface.is_none()) {
+                    anyhow::bail!("Record {} of zone {} has an ipv6_suffix, which needs an AAAA record and ipv6_interface", record.name, zone.name);
+                }
+                match (record.record_type(), &record.data) {
+                    (RecordType::Srv, None) => anyhow::bail!("SRV record {} of zone {} needs a `data` table", record.name, zone.name),
+                    (RecordType::Srv, Some(_)) | (_, None) => (),
+                    (_, Some(_)) => anyhow::bail!("Record {} of zone {} has a `data` table, but only SRV records use one", record.name, zone.name),
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 struct ZoneConfig {
     zone_id: String,
     name: String,
+    /// Used by records that don't set `proxy` themselves.
+    default_proxy: Option<bool>,
+    /// Used by records that don't set `ttl` themselves.
+    default_ttl: Option<u32>,
+    /// Used by records that don't set `type` themselves.
+    default_type: Option<RecordType>,
+    /// Used by records that don't set `create_missing` themselves.
+    default_create_missing: Option<bool>,
+    /// Update this zone through RFC 2136 dynamic updates instead of Cloudflare.
+    /// `zone_id` then only serves to identify the zone in the config.
+    rfc2136: Option<Rfc2136Config>,
     records: Vec<Record>,
 }
 
@@ -32,120 +683,1745 @@ struct ZoneConfig {
 struct RecordInfo {
     id: String,
     name: String,
+    /// Kept as a string since Cloudflare knows more record types than we do.
+    #[serde(rename = "type")]
+    type_: String,
+    #[serde(default)]
+    content: String,
+    /// When the record last changed, used to notice writes by someone else.
+    #[serde(default)]
+    modified_on: Option<String>,
+    #[serde(default)]
+    proxied: bool,
+    /// 1 means automatic.
+    #[serde(default)]
+    ttl: Option<u32>,
+    #[serde(default)]
+    comment: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// An entry of `errors` or `messages` in a Cloudflare response.
+#[derive(Clone, Debug, serde::Deserialize)]
+struct CloudflareMessage {
+    code: u32,
+    message: String,
+}
+
+impl std::fmt::Display for CloudflareMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
-struct CloudflareResponse {
+struct ResultInfo {
+    count: u32,
+    total_count: u32,
+    page: u32,
+    total_pages: u32,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct CloudflareResponse<T> {
     success: bool,
-    errors: Vec<String>,
-    messages: Vec<String>,
-    result: Vec<RecordInfo>
+    errors: Vec<CloudflareMessage>,
+    messages: Vec<CloudflareMessage>,
+    result: Option<T>,
+    result_info: Option<ResultInfo>,
+}
+
+/// Exit code for credentials Cloudflare rejects at startup, so supervisors can tell it from other failures.
+const EXIT_BAD_CREDENTIALS: i32 = 3;
+
+/// First delay before retrying a pass that ran into a Cloudflare outage.
+const OUTAGE_RETRY_BASE: Duration = Duration::from_secs(30);
+
+/// Sends a Cloudflare API request, turning unsuccessful responses into errors.
+fn cloudflare_call<T: serde::de::DeserializeOwned>(client: &Http, request: Request<Body>, what: &str) -> Result<CloudflareResponse<T>, DynsError> {
+    let mut response = client.send(request)?;
+    let status = response.status();
+    if status.is_server_error() {
+        return Err(DynsError::Unavailable { status: status.as_u16() });
+    }
+    let body: CloudflareResponse<T> = match http::parse_json(response.body_mut()) {
+        Ok(body) => body,
+        Err((e, head)) => {
+            log::debug!("Cloudflare answered a request to {} with something that isn't JSON ({}): {}", what, e, head);
+            return Err(DynsError::Unavailable { status: status.as_u16() });
+        }
+    };
+    if !body.success {
+        if matches!(status.as_u16(), 401 | 403) {
+            let errors: Vec<_> = body.errors.iter().map(ToString::to_string).collect();
+            return Err(DynsError::Auth { what: what.to_string(), reason: format!("{}: {}", status, errors.join(", ")) });
+        }
+        let code = body.errors.first().map(|e| e.code);
+        let message = body.errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join(", ");
+        return Err(DynsError::Api { what: what.to_string(), code, message });
+    }
+    if !body.messages.is_empty() {
+        log::debug!("Cloudflare says: {:?}", body.messages);
+    }
+    Ok(body)
+}
+
+/// Makes sure Cloudflare accepts the credentials, with the endpoint matching how we authenticate.
+fn verify_credentials(client: &Http, cfg: &Config) -> Result<(), DynsError> {
+    #[derive(serde::Deserialize)]
+    struct TokenStatus {
+        status: String,
+    }
+    if cfg.authorization.is_none() {
+        let request = cfg.authorize(Request::get("https://api.cloudflare.com/client/v4/user")).body(Body::empty()).expect("Failed to create request");
+        cloudflare_call::<serde_json::Value>(client, request, "verify the API key")?;
+        return Ok(());
+    }
+    let request = cfg.authorize(Request::get("https://api.cloudflare.com/client/v4/user/tokens/verify")).body(Body::empty()).expect("Failed to create request");
+    match cloudflare_call::<TokenStatus>(client, request, "verify the API token")?.result {
+        Some(token) if token.status != "active" => {
+            Err(DynsError::Auth { what: "verify the API token".to_string(), reason: format!("the token is {}", token.status) })
+        }
+        _ => Ok(()),
+    }
 }
 
 /// Taken from https://api.cloudflare.com/#dns-records-for-a-zone-patch-dns-record
 #[derive(Clone, Debug, serde::Serialize)]
 struct UpdateRecordBody<'a> {
-    /* #[serde(rename="type")]
-    type_: String, */
-    content: &'a str,
-    proxy: bool,
+    #[serde(rename="type")]
+    type_: RecordType,
+    /// Only sent when replacing the whole record.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    /// Cloudflare derives the content of SRV records from `data`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'a str>,
+    #[serde(rename = "proxied", skip_serializing_if = "Option::is_none")]
+    proxy: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<SrvData>,
 }
 
-fn get_dns_record_id(client: &mut isahc::HttpClient, cfg: &Config, zone_id: &str, name: &str) -> anyhow::Result<String> {
-    let url = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records", zone_id);
-    let mut response = client.send(Request::get(url)
-                .header("X-auth-email", &cfg.email)
-                .header("x-auth-key", &cfg.auth_key)
-                .body(Body::empty()).expect("Failed to create request"))?;
-    let body: CloudflareResponse = response.json().expect("Failed to parse response");
-    if !body.success {
-        anyhow::bail!("Failed to get DNS record ID: {:?}", body.errors)
+/// Cloudflare's maximum page size for DNS record listings.
+const RECORDS_PER_PAGE: u32 = 100;
+
+/// Fetches every page of a zone's DNS records, as `T`.
+fn list_all_dns_records<T: serde::de::DeserializeOwned>(client: &Http, cfg: &Config, zone_id: &str) -> Result<Vec<T>, DynsError> {
+    let mut records = Vec::new();
+    let mut page = 1;
+    loop {
+        let url = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records?page={}&per_page={}", zone_id, page, RECORDS_PER_PAGE);
+        let body: CloudflareResponse<Vec<T>> = cloudflare_call(client, cfg.authorize(Request::get(url))
+                    .body(Body::empty()).expect("Failed to create request"), "list DNS records")?;
+        records.extend(body.result.unwrap_or_default());
+        match body.result_info {
+            Some(info) if info.page < info.total_pages => {
+                log::debug!("Fetched {} records of zone {} (page {}/{})", info.count, zone_id, info.page, info.total_pages);
+                page += 1;
+            }
+            Some(info) => {
+                log::debug!("Zone {} has {} DNS records", zone_id, info.total_count);
+                break;
+            }
+            None => break,
+        }
     }
-    body.result.into_iter()
-        .find(|info| info.name == name)
-        .map(|info| info.id)
-        .ok_or(anyhow::anyhow!("Did not find any DNS record with name {}", name))
+    Ok(records)
+}
 
+/// Finds all records of the zone `listing` matching both the name and type of `record`.
+///
+/// A name can legitimately carry several records of one type (e.g. round-robin A records),
+/// in which case all of them are returned, unless `strict` is set. Records of other types are
+/// never returned: writing one would change its type and clobber e.g. the MX or TXT records of
+/// the zone apex.
+fn find_dns_records(cfg: &Config, listing: Vec<RecordInfo>, record: &Record) -> Result<Vec<RecordInfo>, DynsError> {
+    let wanted_type = record.record_type().to_string();
+    let (ids, other_types): (Vec<_>, Vec<_>) = listing.into_iter()
+        .filter(|info| info.name == record.name)
+        .partition(|info| info.type_ == wanted_type);
+    match ids.len() {
+        0 => (),
+        1 => return Ok(ids),
+        n if cfg.strict => return Err(DynsError::Record(format!("Found {} {} records named {}, refusing to pick one in strict mode", n, wanted_type, record.name))),
+        n => {
+            log::info!("Found {} {} records named {}, updating all of them", n, wanted_type, record.name);
+            return Ok(ids);
+        }
+    }
+    let Some(info) = other_types.first() else {
+        return Err(DynsError::Record(format!("Did not find any DNS record with name {}", record.name)));
+    };
+    Err(DynsError::Record(format!("Record {} is configured as {} but Cloudflare only has it as {}, not touching that. \
+                                   If this is the zone apex, Cloudflare may be flattening a CNAME there; make the configured \
+                                   type match the existing record, or set create_missing = true to add one of type {} next to it",
+                                  record.name, wanted_type, info.type_, wanted_type)))
 }
 
+/// How often a conditional update re-reads a record that keeps changing before giving up.
+const CONDITIONAL_ATTEMPTS: u32 = 3;
+
+fn update_record(client: &Http, cfg: &Config, zone_id: &str, record: &Record, ip: &str) -> Result<(), DynsError> {
+    if let Some(contents) = record.desired_contents(ip)? {
+        return reconcile_records(client, cfg, zone_id, record, &contents);
+    }
+    let listing = list_all_dns_records::<RecordInfo>(client, cfg, zone_id)?;
+    let wanted_type = record.record_type().to_string();
+    if record.creates_missing() && !listing.iter().any(|info| info.name == record.name && info.type_ == wanted_type) {
+        create_record(client, cfg, zone_id, record, ip)?;
+        log::info!("Created {} record {} pointing to {}", record.record_type(), record.name, ip);
+        return Ok(());
+    }
+    for info in find_dns_records(cfg, listing, record)? {
+        if cfg.conditional_updates {
+            write_record_if_unchanged(client, cfg, zone_id, info, record, ip)?;
+        } else {
+            write_record(client, cfg, zone_id, &info.id, record, ip)?;
+        }
+    }
+
+    log::info!("Successfully updated record {} to {}", record.name, ip);
+    Ok(())
+}
+
+/// Cloudflare has no conditional writes for DNS records, so this compares `modified_on` right before
+/// writing and re-reads the record when someone else wrote it since it was looked up.
+fn write_record_if_unchanged(client: &Http, cfg: &Config, zone_id: &str, mut seen: RecordInfo, record: &Record, ip: &str) -> Result<(), DynsError> {
+    let url = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}", zone_id, seen.id);
+    for attempt in 1..=CONDITIONAL_ATTEMPTS {
+        let current: RecordInfo = cloudflare_call(client, cfg.authorize(Request::get(&url)).body(Body::empty()).expect("Failed to create request"), "read DNS record")?
+            .result.ok_or_else(|| DynsError::Api { what: "read DNS record".to_string(), code: None, message: format!("no record {} returned", seen.id) })?;
+        if current.modified_on == seen.modified_on {
+            return write_record(client, cfg, zone_id, &seen.id, record, ip);
+        }
+        log::warn!("Record {} changed since it was read (now {}), re-reading it ({}/{})",
+                   record.name, current.content, attempt, CONDITIONAL_ATTEMPTS);
+        seen = current;
+    }
+    Err(DynsError::Record(format!("Record {} keeps changing, not overwriting it", record.name)))
+}
+
+/// Makes the records named like `record` hold exactly `contents`, reusing existing records where possible.
+fn reconcile_records(client: &Http, cfg: &Config, zone_id: &str, record: &Record, contents: &[String]) -> Result<(), DynsError> {
+    let wanted_type = record.record_type().to_string();
+    let existing: Vec<_> = list_all_dns_records::<RecordInfo>(client, cfg, zone_id)?.into_iter()
+        .filter(|info| info.name == record.name && info.type_ == wanted_type)
+        .collect();
+    let mut missing = contents.iter().filter(|content| !existing.iter().any(|info| &info.content == *content));
+    let extra = existing.iter().filter(|info| !contents.contains(&info.content));
+    for info in extra {
+        match missing.next() {
+            Some(content) => {
+                write_record(client, cfg, zone_id, &info.id, record, content)?;
+                log::info!("Changed {} record {} from {} to {}", wanted_type, record.name, info.content, content);
+            }
+            None => {
+                let url = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}", zone_id, info.id);
+                cloudflare_call::<serde_json::Value>(client, cfg.authorize(Request::delete(url)).body(Body::empty()).expect("Failed to create request"), "delete DNS record")?;
+                log::info!("Deleted {} record {} pointing to {}", wanted_type, record.name, info.content);
+            }
+        }
+    }
+    for content in missing {
+        create_record(client, cfg, zone_id, record, content)?;
+        log::info!("Created {} record {} pointing to {}", wanted_type, record.name, content);
+    }
+    Ok(())
+}
 
+/// Creates a record named like `record` holding `content`, with all of its settings.
+fn create_record(client: &Http, cfg: &Config, zone_id: &str, record: &Record, content: &str) -> Result<(), DynsError> {
+    let url = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records", zone_id);
+    let data = record.data.as_ref().map(|data| SrvData {
+        target: data.target.replace("${ip}", content),
+        ..data.clone()
+    });
+    let body = UpdateRecordBody {
+        type_: record.record_type(),
+        name: Some(&record.name),
+        content: if data.is_some() { None } else { Some(content) },
+        proxy: Some(record.proxied()),
+        ttl: Some(record.ttl.unwrap_or(1)),
+        comment: record.comment.as_deref(),
+        tags: Some(&record.cloudflare_tags).filter(|tags| !tags.is_empty()).map(Vec::as_slice),
+        data,
+    };
+    cloudflare_call::<serde_json::Value>(client, cfg.authorize(Request::post(url))
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&body).expect("Failed to serialize request body"))).expect("Failed to create request"), "create DNS record")?;
+    Ok(())
+}
 
-fn update_record(client: &mut isahc::HttpClient, cfg: &Config, zone_id: &str, record: &Record, ip: &str) -> anyhow::Result<()> {
-    let record_id = get_dns_record_id(client, cfg, zone_id, &record.name)?;
+fn write_record(client: &Http, cfg: &Config, zone_id: &str, record_id: &str, record: &Record, ip: &str) -> Result<(), DynsError> {
     let url = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}", zone_id, record_id);
-    client.send(Request::patch(url)
-                .header("X-auth-email", &cfg.email)
-                .header("x-auth-key", &cfg.auth_key)
-                .header("Authorization", format!("Bearer {}", cfg.authorization))
+    let data = record.data.as_ref().map(|data| SrvData {
+        target: data.target.replace("${ip}", ip),
+        ..data.clone()
+    });
+    let tags = Some(record.cloudflare_tags.as_slice());
+    let body = match cfg.update_method {
+        UpdateMethod::Patch => UpdateRecordBody {
+            type_: record.record_type(),
+            name: None,
+            content: None,
+            proxy: cfg.syncs(SyncField::Proxied).then(|| record.proxied()),
+            ttl: record.ttl.filter(|_| cfg.syncs(SyncField::Ttl)),
+            comment: cfg.syncs(SyncField::Comment).then(|| record.comment.as_deref().unwrap_or_default()),
+            tags: tags.filter(|_| cfg.syncs(SyncField::Tags)),
+            data: None,
+        },
+        // A replaced record loses whatever we don't send, so send everything; a TTL of 1 means automatic.
+        UpdateMethod::Put => UpdateRecordBody {
+            type_: record.record_type(),
+            name: Some(record.name.as_str()),
+            content: None,
+            proxy: Some(record.proxied()),
+            ttl: Some(record.ttl.unwrap_or(1)),
+            comment: record.comment.as_deref(),
+            tags: tags.filter(|tags| !tags.is_empty()),
+            data: None,
+        },
+    };
+    let request = match cfg.update_method {
+        UpdateMethod::Patch => Request::patch(url),
+        UpdateMethod::Put => Request::put(url),
+    };
+    cloudflare_call::<serde_json::Value>(client, cfg.authorize(request)
                 .header("Content-Type", "application/json")
                 .body(Body::from(serde_json::to_string(&UpdateRecordBody {
-                    content: ip,
-                    proxy: record.proxy,
-                }).expect("Failed to serialize request body"))).expect("Failed to create a request"))?;
+                    content: if data.is_some() { None } else { Some(ip) },
+                    data,
+                    ..body
+                }).expect("Failed to serialize request body"))).expect("Failed to create a request"), "update DNS record")?;
+    Ok(())
+}
 
-    log::info!("Successfully updated record {} to {}", record.name, ip);
+struct Cloudflare<'a> {
+    client: &'a Http,
+    cfg: &'a Config,
+    zone_id: &'a str,
+}
+
+impl DnsProvider for Cloudflare<'_> {
+    fn update_record(&self, record: &Record, ip: &str) -> Result<(), DynsError> {
+        update_record(self.client, self.cfg, self.zone_id, record, ip)
+    }
+}
+
+/// Polls the configured resolver until `record` resolves to `ip`, warning if it doesn't in time.
+/// Gives up at `pass_deadline` too, so verifying can't stretch the pass past `pass_timeout_secs`.
+fn verify_propagation(verify: &VerifyPropagation, record: &Record, ip: &str, pass_deadline: Option<Instant>) -> Result<(), DynsError> {
+    let ipv6 = match record.record_type() {
+        RecordType::A => false,
+        RecordType::Aaaa => true,
+        _ => return Ok(()),
+    };
+    if record.proxied() {
+        log::debug!("Not verifying {}: proxied records resolve to Cloudflare's addresses", record.name);
+        return Ok(());
+    }
+    let expected: IpAddr = ip.parse().map_err(|_| DynsError::Record(format!("{} is not an IP address", ip)))?;
+    let resolver = verify.resolver()?;
+    let deadline = Instant::now() + Duration::from_secs(verify.timeout_secs.unwrap_or(60));
+    let deadline = pass_deadline.map_or(deadline, |pass_deadline| deadline.min(pass_deadline));
+    let mut last_seen = Vec::new();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+        match dns::resolve(resolver, &record.name, ipv6, left.min(Duration::from_secs(5))) {
+            Ok(addresses) if addresses.contains(&expected) => {
+                log::info!("Verified that {} resolves to {} at {}", record.name, ip, resolver);
+                return Ok(());
+            }
+            Ok(addresses) => last_seen = addresses,
+            Err(e) => log::debug!("Failed to resolve {} at {}: {}", record.name, resolver, e),
+        }
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()).min(Duration::from_secs(2)));
+    }
+    log::warn!("{} still doesn't resolve to {} at {} (got {:?}); the update may not have taken effect or is being cached",
+               record.name, ip, resolver, last_seen);
     Ok(())
 }
 
-fn get_current_ip(client: &mut isahc::HttpClient) -> anyhow::Result<String> {
-    Ok(client.get("https://api.ipify.org/").context("Failed to get new IP address")?
-       .text()
-       .map(|t| t.trim().to_string())?)
+/// What happened to one record during an update pass.
+#[derive(Clone, Debug, serde::Serialize)]
+struct RecordReport {
+    zone: String,
+    name: String,
+    #[serde(rename = "type")]
+    type_: RecordType,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+struct PassReport {
+    /// Whether nothing failed and the pass wasn't aborted. Records held back by a cooldown don't count
+    /// against it, see [`PassReport::held`].
+    complete: bool,
+    /// Whether Cloudflare seemed to be down, so the pass should be retried soon but not hammered.
+    outage: bool,
+    records: Vec<RecordReport>,
+}
+
+impl PassReport {
+    /// The records `min_update_interval_secs` held back, by zone, name and type. Nothing failed for
+    /// them, but they still have to be written by a later pass.
+    fn held(&self) -> Vec<(String, String, RecordType)> {
+        self.records.iter()
+            .filter(|record| matches!(record.outcome, Outcome::Held))
+            .map(|record| (record.zone.clone(), record.name.clone(), record.type_))
+            .collect()
+    }
+
+    /// Whether every record now has what this pass was meant to publish.
+    fn done(&self) -> bool {
+        self.complete && !self.records.iter().any(|record| matches!(record.outcome, Outcome::Held))
+    }
+}
+
+/// What records get pointed at. `ip` comes from `ip_providers` and is `None` with IPv4 disabled;
+/// `ipv6` is used for AAAA records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Addresses {
+    ip: Option<IpAddr>,
+    ipv6: Option<InterfaceAddress>,
+}
+
+impl Addresses {
+    /// The address reported in events: the detected IP, or else the IPv6 one.
+    fn primary(&self) -> Option<IpAddr> {
+        self.ip.or(self.ipv6.map(|ipv6| IpAddr::V6(ipv6.addr)))
+    }
+}
 
-        
+impl std::fmt::Display for Addresses {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.ip, self.ipv6) {
+            (Some(ip), Some(ipv6)) if ip != IpAddr::V6(ipv6.addr) => write!(f, "{} and {}", ip, ipv6.addr),
+            _ => match self.primary() {
+                Some(ip) => write!(f, "{}", ip),
+                None => write!(f, "no address"),
+            },
+        }
+    }
+}
+
+/// Checks that every record of a `transactional` pass can be updated, without changing any.
+fn validate_pass(client: &Http, cfg: &Config, addresses: Addresses, wan: &WanAddresses) -> anyhow::Result<()> {
+    let mut zones: HashMap<&str, Vec<RecordInfo>> = HashMap::new();
+    for (zone, record) in cfg.ordered_records() {
+        let (record, address) = match record.resolve(&zone.name, addresses, wan) {
+            Some(resolved) => resolved,
+            None if record.record_type() == RecordType::A && !cfg.ipv4_enabled() && record.wan_sources.is_empty() => continue,
+            None => anyhow::bail!("No address known for {} yet", record.name),
+        };
+        let record = &*record;
+        if record.desired_contents(&record.content(&address))?.is_some() || zone.rfc2136.is_some() {
+            continue;
+        }
+        let listing = match zones.entry(&zone.zone_id) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => entry.insert(list_all_dns_records::<RecordInfo>(client, cfg, &zone.zone_id)?),
+        };
+        let wanted_type = record.record_type().to_string();
+        if record.creates_missing() && !listing.iter().any(|info| info.name == record.name && info.type_ == wanted_type) {
+            continue;
+        }
+        find_dns_records(cfg, listing.clone(), record).with_context(|| format!("Record {} of zone {} can't be updated", record.name, zone.name))?;
+    }
+    Ok(())
+}
+
+/// Updates every configured record, or just those in `only`, stopping early if the pass exceeds
+/// `pass_timeout_secs`. Requests still running at that point time out.
+fn update_all(client: &Http, cfg: &Config, addresses: Addresses, wan: &WanAddresses, history: &mut History,
+              only: Option<&[(String, String, RecordType)]>) -> PassReport {
+    let deadline = cfg.pass_timeout_secs.map(|secs| Instant::now() + Duration::from_secs(secs));
+    client.set_deadline(deadline);
+    let report = update_records(client, cfg, addresses, wan, history, only, deadline);
+    client.set_deadline(None);
+    report
+}
+
+fn update_records(client: &Http, cfg: &Config, addresses: Addresses, wan: &WanAddresses, history: &mut History,
+                  only: Option<&[(String, String, RecordType)]>, deadline: Option<Instant>) -> PassReport {
+    let mut report = PassReport { complete: true, outage: false, records: Vec::new() };
+    if cfg.transactional {
+        if let Err(e) = validate_pass(client, cfg, addresses, wan) {
+            dedup::log(log::Level::Error, "transactional pass", format!("Skipping the whole update pass: {:#}", e));
+            report.complete = false;
+            report.outage = matches!(error::kind(&e), Some(DynsError::Unavailable { .. }));
+            for (zone, record) in cfg.ordered_records() {
+                let outcome = Outcome::Failed(format!("Pass skipped: {:#}", e));
+                history.record(&zone.name, &record.name, outcome.clone());
+                report.records.push(RecordReport { zone: zone.name.clone(), name: record.name.clone(), type_: record.record_type(), outcome });
+            }
+            return report;
+        }
+        dedup::clear("transactional pass");
+    }
+    for (zone, record) in cfg.ordered_records() {
+        if only.is_some_and(|only| !only.iter().any(|(z, name, type_)| *z == zone.name && *name == record.name && *type_ == record.record_type())) {
+            continue;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            log::error!("Update pass took longer than {}s, aborting it until the next cycle", cfg.pass_timeout_secs.unwrap_or_default());
+            report.complete = false;
+            return report;
+        }
+        let provider: Box<dyn DnsProvider> = match &zone.rfc2136 {
+            Some(rfc2136) => Box::new(Rfc2136Provider::new(rfc2136, &zone.name)),
+            None => Box::new(Cloudflare { client, cfg, zone_id: &zone.zone_id }),
+        };
+        let (record, address) = match record.resolve(&zone.name, addresses, wan) {
+            Some(resolved) => resolved,
+            None if record.record_type() == RecordType::A && !cfg.ipv4_enabled() && record.wan_sources.is_empty() => {
+                log::debug!("IPv4 is disabled, leaving A record {} alone", record.name);
+                continue;
+            }
+            None => {
+                log::warn!("No address known for {} yet, skipping it this pass", record.name);
+                report.complete = false;
+                continue;
+            }
+        };
+        let record = &*record;
+        let key = format!("update {} {}", zone.name, record.name);
+        let interval = record.min_update_interval_secs.or(cfg.min_update_interval_secs).map(Duration::from_secs);
+        if let Some(left) = interval.and_then(|interval| cooldown::remaining(&key, interval)) {
+            log::debug!("Record {} was written less than {}s ago, holding it off for {}s more",
+                        record.name, interval.unwrap_or_default().as_secs(), left.as_secs());
+            metrics::record_update(&zone.name, &record.name, "held", &address);
+            history.record(&zone.name, &record.name, Outcome::Held);
+            report.records.push(RecordReport { zone: zone.name.clone(), name: record.name.clone(), type_: record.record_type(), outcome: Outcome::Held });
+            continue;
+        }
+        let result = match zone.rfc2136 {
+            // No need to pile more requests onto an outage.
+            None if report.outage => None,
+            _ => Some(provider.update_record(record, &record.content(&address))),
+        };
+        let outcome = match result {
+            None => {
+                report.complete = false;
+                Outcome::Failed("Skipped while Cloudflare is having issues".to_string())
+            }
+            Some(Ok(())) => {
+                dedup::clear(&key);
+                cooldown::wrote(&key);
+                if let Some(verify) = cfg.verify_propagation.as_ref().filter(|_| record.content_template.is_none()) {
+                    if let Err(e) = verify_propagation(verify, record, &address, deadline) {
+                        log::warn!("Could not verify propagation of {}: {}", record.name, e);
+                    }
+                }
+                Outcome::Updated
+            }
+            Some(Err(e @ DynsError::Unavailable { .. })) => {
+                dedup::log(log::Level::Warn, "cloudflare outage", e.to_string());
+                report.complete = false;
+                report.outage = true;
+                Outcome::Failed(e.to_string())
+            }
+            Some(Err(e)) => {
+                dedup::log(log::Level::Error, &key, format!("An error happened while updating record {} of zone {}: {}", record.name, zone.name, e));
+                report.complete = false;
+                Outcome::Failed(e.to_string())
+            }
+        };
+        metrics::record_update(&zone.name, &record.name, if matches!(outcome, Outcome::Updated) { "success" } else { "failure" }, &address);
+        history.record(&zone.name, &record.name, outcome.clone());
+        report.records.push(RecordReport { zone: zone.name.clone(), name: record.name.clone(), type_: record.record_type(), outcome });
+    }
+    report
+}
+
+/// Why [`sleep`] returned.
+enum Wake {
+    Timeout,
+    ConfigChanged,
+    UpdateRequested,
+    /// The wall clock moved by this many seconds more than the monotonic one, e.g. because the
+    /// machine was suspended. Negative if it was set back.
+    ClockJumped(i64),
+}
+
+/// How far the wall clock may drift from the monotonic clock within one tick of [`sleep`]
+/// before it counts as a jump.
+const CLOCK_JUMP: Duration = Duration::from_secs(30);
+
+/// Sleeps for `duration`, waking up every second to handle signals and config changes.
+///
+/// The monotonic clock stops while the machine is suspended, so the wall clock is checked on
+/// every tick too: after a resume the network has likely changed and the IP is checked right away.
+fn sleep(duration: Duration, history: &History, mut watcher: Option<&mut FileWatcher>) -> Wake {
+    let until = Instant::now() + duration;
+    while let Some(left) = until.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+        let (instant, wall) = (Instant::now(), SystemTime::now());
+        std::thread::sleep(left.min(Duration::from_secs(1)));
+        let elapsed = instant.elapsed();
+        let jump = match SystemTime::now().duration_since(wall) {
+            Ok(wall_elapsed) => wall_elapsed.saturating_sub(elapsed).as_secs() as i64,
+            Err(e) => -((e.duration() + elapsed).as_secs() as i64),
+        };
+        if jump.unsigned_abs() >= CLOCK_JUMP.as_secs() {
+            return Wake::ClockJumped(jump);
+        }
+        if signals::take_summary_request() {
+            history.log_summary();
+        }
+        if signals::take_update_request() {
+            return Wake::UpdateRequested;
+        }
+        if watcher.as_mut().is_some_and(|watcher| watcher.poll()) {
+            return Wake::ConfigChanged;
+        }
+    }
+    Wake::Timeout
+}
+
+/// Observable things that happen while running, kept apart from plain logging so
+/// detection and application can be reported separately.
+#[derive(serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    IpChanged { old_ip: IpAddr, new_ip: IpAddr },
+    /// Sent after every update pass. `old_ip` is the address the previous complete pass published.
+    RecordsUpdated { old_ip: Option<IpAddr>, new_ip: IpAddr, records: &'a [RecordReport] },
+    /// Something went wrong outside of updating a record, e.g. the IP couldn't be detected.
+    Error { message: &'a str },
+    /// Only sent by `--test-webhook`.
+    Test,
+}
+
+/// Logs `event` and forwards it to the webhook and event socket, if configured.
+fn emit(client: &Http, cfg: &Config, event: Event) {
+    match &event {
+        Event::IpChanged { old_ip, new_ip } => log::info!("ip_changed: {} -> {}", old_ip, new_ip),
+        Event::RecordsUpdated { new_ip, records, .. } => {
+            let updated = records.iter().filter(|record| matches!(record.outcome, Outcome::Updated)).count();
+            let held = records.iter().filter(|record| matches!(record.outcome, Outcome::Held)).count();
+            match held {
+                0 => log::info!("records_updated: {} of {} records point to {}", updated, records.len(), new_ip),
+                _ => log::info!("records_updated: {} of {} records point to {}, {} held back by min_update_interval_secs", updated, records.len(), new_ip, held),
+            }
+        }
+        // Already logged where it happened.
+        Event::Error { .. } | Event::Test => (),
+    }
+    #[cfg(unix)]
+    if cfg.event_socket.is_some() {
+        match serde_json::to_string(&event) {
+            Ok(line) => socket::broadcast(&line),
+            Err(e) => log::warn!("Failed to serialize event: {}", e),
+        }
+    }
+    if let Some(url) = &cfg.webhook_url {
+        if let Err(e) = send_webhook(client, url, &event) {
+            log::warn!("Failed to notify webhook {}: {}", url, e);
+        }
+    }
+}
+
+fn send_webhook(client: &Http, url: &str, event: &Event) -> anyhow::Result<isahc::http::StatusCode> {
+    let response = client.send(Request::post(url)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(event)?))?)?;
+    if !response.status().is_success() {
+        anyhow::bail!("Webhook answered with {}", response.status());
+    }
+    Ok(response.status())
+}
+
+/// Asks each of `providers` in turn until one answers with an address of the right `version`.
+fn get_current_ip(client: &Http, cfg: &Config, providers: &[IpProvider], version: IpVersion) -> Result<IpAddr, DynsError> {
+    let mut last_error = None;
+    let mut unreachable = 0;
+    for provider in providers {
+        match get_ip_from(client, provider, version.clone(), None) {
+            Ok(ip) if !matches!((&version, ip), (IpVersion::Any, _) | (IpVersion::V4, IpAddr::V4(_)) | (IpVersion::V6, IpAddr::V6(_))) => {
+                log::warn!("{} reported {}, which is the wrong address family; trying the next provider", provider.url, ip);
+                last_error = Some(format!("{} is not of the expected address family", ip));
+            }
+            Ok(ip) if !cfg.ip_allowed(ip) => {
+                log::warn!("{} reported {}, which is outside allowed_cidrs; not publishing it", provider.url, ip);
+                last_error = Some(format!("{} is outside allowed_cidrs", ip));
+            }
+            Ok(ip) if Cidr::CGNAT.contains(ip) && matches!(cfg.on_cgnat, CgnatPolicy::Refuse) => {
+                dedup::log(log::Level::Error, "cgnat", format!("{} reported {}, which is behind carrier-grade NAT and unreachable from the internet; \
+                                                             not publishing it (on_cgnat = \"refuse\")", provider.url, ip));
+                last_error = Some(format!("{} is a carrier-grade NAT address", ip));
+            }
+            Ok(ip) if Cidr::CGNAT.contains(ip) => {
+                dedup::log(log::Level::Warn, "cgnat", format!("{} reported {}, which is behind carrier-grade NAT: your ISP doesn't give you a public \
+                                                            address, so the records won't be reachable. Publishing it anyway; set on_cgnat = \"refuse\" to skip it", provider.url, ip));
+                return Ok(ip);
+            }
+            Ok(ip) => {
+                dedup::clear(&provider.url);
+                return Ok(ip);
+            }
+            Err(e) => {
+                dedup::log(log::Level::Warn, &provider.url, format!("Failed to get IP address from {}: {}", provider.url, e));
+                unreachable += usize::from(e.is_network());
+                last_error = Some(e.to_string());
+            }
+        }
+    }
+    if unreachable > 0 && unreachable == providers.len() {
+        return Err(DynsError::Network);
+    }
+    Err(DynsError::IpDetection(last_error.unwrap_or_else(|| "No IP providers configured".to_string())))
+}
+
+/// How often waiting for `wait_for_interface` is logged at most.
+const INTERFACE_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Polls until `interface` is up with a global address, giving up after `timeout`.
+fn wait_for_interface(interface: &str, timeout: Duration) {
+    let started = Instant::now();
+    let mut logged: Option<Instant> = None;
+    loop {
+        let missing = match interface::global_addresses(interface) {
+            Ok(Some(addresses)) if !addresses.is_empty() => {
+                let addresses: Vec<_> = addresses.iter().map(ToString::to_string).collect();
+                log::info!("{} is up with {} after {}s", interface, addresses.join(", "), started.elapsed().as_secs());
+                return;
+            }
+            Ok(Some(_)) => "has no global address yet",
+            Ok(None) => "doesn't exist yet",
+            Err(e) => {
+                log::warn!("Can't tell whether {} is up, not waiting for it: {}", interface, e);
+                return;
+            }
+        };
+        if started.elapsed() >= timeout {
+            log::warn!("Gave up waiting for {} after {}s, it {}", interface, timeout.as_secs(), missing);
+            return;
+        }
+        if logged.is_none_or(|logged| logged.elapsed() >= INTERFACE_LOG_INTERVAL) {
+            log::info!("Waiting for {}, it {}", interface, missing);
+            logged = Some(Instant::now());
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// How often being offline is logged at most.
+const OFFLINE_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Like [`get_current_ip`], but never gives up: the network may simply not be up yet.
+/// Retries use exponential backoff with jitter, except while offline, see `offline_poll_secs`.
+fn detect_ip(client: &Http, cfg: &Config, history: &History) -> IpAddr {
+    let max = Duration::from_secs(cfg.ip_retry_max_secs.unwrap_or(300));
+    let mut delay = Duration::from_secs(cfg.ip_retry_base_secs.unwrap_or(5)).min(max);
+    let mut offline_since: Option<Instant> = None;
+    let mut offline_logged: Option<Instant> = None;
+    loop {
+        match get_current_ip(client, cfg, &cfg.ip_providers, cfg.ip_version()) {
+            Ok(ip) => {
+                dedup::clear("detect ip");
+                if let Some(since) = offline_since {
+                    log::info!("The network is back after {}s", since.elapsed().as_secs());
+                }
+                return ip;
+            }
+            Err(DynsError::Network) => {
+                let since = *offline_since.get_or_insert_with(Instant::now);
+                if offline_logged.is_none_or(|logged| logged.elapsed() >= OFFLINE_LOG_INTERVAL) {
+                    log::warn!("No network for {}s, waiting for it to come back", since.elapsed().as_secs());
+                    offline_logged = Some(Instant::now());
+                }
+                // The webhook isn't reachable either, so no error event.
+                sleep(Duration::from_secs(cfg.offline_poll_secs.unwrap_or(15)), history, None);
+            }
+            Err(e) => {
+                let jittered = delay.mul_f64(0.5 + fastrand::f64() / 2.0);
+                dedup::log(log::Level::Warn, "detect ip", format!("Failed to detect the current IP address: {}", e));
+                log::debug!("Retrying IP detection in {:.1}s", jittered.as_secs_f32());
+                emit(client, cfg, Event::Error { message: &format!("Failed to detect the current IP address: {:#}", e) });
+                sleep(jittered, history, None);
+                delay = (delay * 2).min(max);
+            }
+        }
+    }
+}
+
+/// The IPv6 address from `ipv6_interface` or, failing that, `ipv6_providers`. Unlike [`detect_ip`] this
+/// doesn't retry, so a flaky IPv6 setup can't hold up IPv4 updates.
+fn detect_ipv6(client: &Http, cfg: &Config) -> Option<InterfaceAddress> {
+    if let Some(interface) = &cfg.ipv6_interface {
+        return match ipv6::global_address(interface) {
+            Ok(address) => {
+                dedup::clear("detect ipv6");
+                Some(address)
+            }
+            Err(e) => {
+                dedup::log(log::Level::Warn, "detect ipv6", format!("Failed to read the IPv6 address of {}: {}", interface, e));
+                None
+            }
+        };
+    }
+    if !cfg.enable_ipv6 {
+        return None;
+    }
+    match get_current_ip(client, cfg, &cfg.ipv6_providers, IpVersion::V6) {
+        Ok(IpAddr::V6(addr)) => {
+            dedup::clear("detect ipv6");
+            Some(InterfaceAddress { addr, prefix_len: 128 })
+        }
+        Ok(ip) => {
+            log::warn!("IPv6 providers reported {}, which is not an IPv6 address", ip);
+            None
+        }
+        Err(e) => {
+            dedup::log(log::Level::Warn, "detect ipv6", format!("Failed to detect the current IPv6 address: {}", e));
+            None
+        }
+    }
+}
+
+/// Detects every enabled kind of address, retrying IPv4 detection until it succeeds.
+fn detect_addresses(client: &Http, cfg: &Config, history: &History) -> Addresses {
+    Addresses {
+        ip: cfg.ipv4_enabled().then(|| detect_ip(client, cfg, history)),
+        ipv6: detect_ipv6(client, cfg),
+    }
+}
+
+/// Like [`detect_addresses`], but failing instead of retrying.
+fn detect_addresses_once(client: &Http, cfg: &Config) -> anyhow::Result<Addresses> {
+    let ip = match cfg.ipv4_enabled() {
+        true => Some(get_current_ip(client, cfg, &cfg.ip_providers, cfg.ip_version())?),
+        false => None,
+    };
+    Ok(Addresses { ip, ipv6: detect_ipv6(client, cfg) })
+}
+
+/// Asks every uplink in `wan_sources` for its address; with `wan_policy = "failover"` only until one answers.
+fn detect_wan(client: &Http, cfg: &Config) -> WanAddresses {
+    let mut wan = WanAddresses::new();
+    for (zone, record) in cfg.ordered_records().into_iter().filter(|(_, record)| !record.wan_sources.is_empty()) {
+        let version = match record.record_type() {
+            RecordType::A => IpVersion::V4,
+            RecordType::Aaaa => IpVersion::V6,
+            _ => IpVersion::Any,
+        };
+        let mut found = Vec::new();
+        for source in &record.wan_sources {
+            let provider = match &source.url {
+                Some(url) => IpProvider { url: url.clone(), headers: BTreeMap::new(), min_interval_secs: None },
+                None => cfg.ip_providers.first().cloned().unwrap_or_else(|| default_ip_providers().remove(0)),
+            };
+            let uplink = source.interface.as_deref().unwrap_or("the default route");
+            let key = format!("wan {} {}", record.name, uplink);
+            match get_ip_from(client, &provider, version.clone(), source.interface.as_deref()) {
+                Ok(ip) if !cfg.ip_allowed(ip) => log::warn!("{} reported {} over {}, which is outside allowed_cidrs", provider.url, ip, uplink),
+                Ok(ip) => {
+                    dedup::clear(&key);
+                    if !found.contains(&ip) {
+                        found.push(ip);
+                    }
+                    if record.wan_policy == WanPolicy::Failover {
+                        break;
+                    }
+                }
+                Err(e) => dedup::log(log::Level::Warn, &key, format!("Failed to detect the address of {} over {}: {:#}", record.name, uplink, e)),
+            }
+        }
+        wan.insert((zone.name.clone(), record.name.clone(), record.record_type()), found);
+    }
+    wan
+}
+
+/// Connects to `provider` over `version` only, so echo services can't answer with the other family.
+fn get_ip_from(client: &Http, provider: &IpProvider, version: IpVersion, interface: Option<&str>) -> Result<IpAddr, DynsError> {
+    let mut request = Request::get(&provider.url).ip_version(version);
+    if let Some(interface) = interface {
+        request = request.interface(isahc::config::NetworkInterface::name(interface));
+    }
+    for (name, value) in &provider.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let text = client.send(request.body(Body::empty()).expect("Failed to create request"))?.text()?;
+    let text = text.trim();
+    text.parse().map_err(|_| DynsError::IpDetection(format!("Response {:?} is not an IP address", text)))
 }
 use clap::Parser;
-use simplelog::{CombinedLogger, SimpleLogger, WriteLogger, SharedLogger};
+use simplelog::CombinedLogger;
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit: ", env!("VERGEN_GIT_SHA"),
+    "\nbuilt: ", env!("VERGEN_BUILD_DATE"),
+    "\nrustc: ", env!("VERGEN_RUSTC_SEMVER"),
+);
+
 #[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, long_version = LONG_VERSION, about, long_about = None)]
 struct Cli {
-    #[clap(short, long, help="Where config file is (defaults to /etc/dyns.toml)")]
+    #[clap(short, long, help="Where config file is, `-` for stdin (defaults to $DYNS_CONFIG, then /etc/dyns.toml, or %PROGRAMDATA%\\dyns\\dyns.toml on Windows)")]
     config: Option<String>,
-    #[clap(short, long, help="Where error logs should be written (defaults to /var/log/dyns.log)")]
+    #[clap(short, long, help="Where error logs should be written, %Y, %m, %d and %H are replaced by the current date (defaults to $DYNS_LOG_FILE, then the config file, then /var/log/dyns.log, or %PROGRAMDATA%\\dyns\\dyns.log on Windows)")]
     log_file: Option<String>,
+    #[clap(long, value_enum, help="Format of the config file (guessed from its extension by default)")]
+    format: Option<ConfigFormat>,
+    #[clap(long, help="Only log to the console, not to a file")]
+    no_log_file: bool,
+    #[clap(short, long, help="Only print warnings and errors to the console; the log file is unaffected")]
+    quiet: bool,
+    #[clap(short, long, conflicts_with="quiet", help="Also print debug messages to the console, like how long each HTTP request took")]
+    verbose: bool,
+    #[clap(long, help="Container-friendly defaults, also enabled by $DYNS_DOCKER: read the config from stdin unless -c or $DYNS_CONFIG is given, log only to stdout")]
+    docker: bool,
+    #[clap(long, help="Never use colors in console output")]
+    no_color: bool,
+    #[clap(long, help="Cloudflare account email, overrides the config file")]
+    email: Option<String>,
+    #[clap(long, help="Cloudflare API key, overrides the config file. Insecure: visible to other users in `ps`, prefer the config file in production")]
+    auth_key: Option<String>,
+    #[clap(long, help="Cloudflare API token, overrides `authorization` in the config file. Insecure: visible to other users in `ps`, prefer the config file in production")]
+    token: Option<String>,
+    #[clap(long, value_name="FILE", conflicts_with="replay-http", help="Record all HTTP interactions into a cassette file, for debugging and fixtures")]
+    record_http: Option<String>,
+    #[clap(long, value_name="FILE", help="Answer HTTP requests from a recorded cassette file instead of the network")]
+    replay_http: Option<String>,
+    #[clap(long, help="Fork into the background instead of running in the foreground")]
+    daemonize: bool,
+    #[clap(long, global=true, help="Keep the PID in this file while running, for `dyns trigger` to read (defaults to /run/dyns.pid, which is only written with --daemonize)")]
+    pid_file: Option<String>,
+    /// Relative paths in the config resolve against this rather than the working directory,
+    /// which `--daemonize` changes to /.
+    #[clap(skip)]
+    base_dir: Option<PathBuf>,
+    #[clap(long, help="Run one regular update pass and exit, failing if any record wasn't updated. Still honors initial_delay_secs, IP detection retries and the update window")]
+    once: bool,
+    #[clap(long, conflicts_with="once", help="Like --once, but without any waiting: no initial delay or wait_for_interface, a single IP detection attempt and no update window")]
+    no_wait: bool,
+    #[clap(long, help="Only process the zones of this profile, besides the top-level zones (defaults to default_profile, else all profiles)")]
+    profile: Option<String>,
+    #[clap(long, value_name="ID_OR_NAME", help="Only process the zone with this zone_id or name")]
+    zone: Option<String>,
+    #[clap(long, value_name="NAME", help="Only process records with this name")]
+    record: Option<String>,
+    #[clap(long, value_name="TAGS", value_delimiter=',', help="Only process records with any of these comma-separated tags")]
+    only_tags: Vec<String>,
+    #[clap(long, requires="only-tags", help="With --only-tags, only process records that have all of the tags")]
+    all_tags: bool,
+    #[clap(long, value_name="BOOL", help="Force the proxied setting of every proxiable Cloudflare record, whatever the config says (also $DYNS_OVERRIDE_PROXY)")]
+    override_proxy: Option<bool>,
+    #[clap(long, conflicts_with_all=&["once", "no-wait", "test-webhook"], help="Detect the IP once, print how each record differs from what would be published and exit without changing anything")]
+    dry_run: bool,
+    #[clap(long, help="Send a test event to webhook_url, report how it answered and exit")]
+    test_webhook: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Print the last published IP and when each record was last updated, from the state file
+    Stats,
+    /// Make the dyns daemon named by --pid-file update all records right away, e.g. from a DHCP client hook
+    Trigger,
+    /// Save every DNS record of the configured Cloudflare zones as JSON, for restoring them by hand if needed
+    Backup {
+        #[clap(help="File to write the backup to")]
+        output: PathBuf,
+    },
+    /// Check the config, log files, IP detection, credentials, zones and records, with hints for whatever fails
+    Doctor,
 }
 
-fn init_logger(log_file: impl AsRef<Path>) {
-    use log::LevelFilter;
-    use simplelog::Config;
-    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![SimpleLogger::new(LevelFilter::Info, Config::default())];
-    match std::fs::File::create(log_file) {
-        Ok(file) => loggers.push(WriteLogger::new(LevelFilter::Error, Config::default(), file)),
-        Err(e) => { log::error!("Failed to open error log file: {}", e); },
+/// Sets up one logger per output. Outputs that can't be opened are skipped and reported once logging works.
+fn init_logger(outputs: &[LogOutput]) {
+    let mut loggers = Vec::new();
+    let mut errors = Vec::new();
+    for output in outputs {
+        match output.build() {
+            Ok(logger) => loggers.push(logger),
+            Err(e) => errors.push((output, e)),
+        }
     }
     CombinedLogger::init(loggers).unwrap();
+    for (output, e) in errors {
+        log::error!("Failed to open log output {}, skipping it: {}", output, e);
+    }
 }
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Json,
+}
 
-    let cfg = std::fs::read_to_string(cli.config.as_deref().unwrap_or(CONFIG_FILE))?;
-    let mut cfg = toml::from_str::<Config>(&cfg)?;
+impl Cli {
+    /// Makes the paths given on the command line absolute, and remembers the working directory
+    /// for the ones in the config.
+    fn pin_paths(&mut self) -> anyhow::Result<()> {
+        let base = std::env::current_dir().context("Failed to get the working directory")?;
+        for path in [&mut self.config, &mut self.log_file, &mut self.pid_file, &mut self.record_http, &mut self.replay_http].into_iter().flatten() {
+            absolutize(&base, path);
+        }
+        self.base_dir = Some(base);
+        Ok(())
+    }
 
-    if cfg.zones.len() == 0 {
-        anyhow::bail!("No zones specified");
+    fn config_path(&self) -> &str {
+        self.config.as_deref().unwrap_or(paths::config_file())
     }
 
-    init_logger(cli.log_file.or(cfg.log_file.take()).as_deref().unwrap_or(LOG_FILE));
+    fn config_format(&self) -> anyhow::Result<ConfigFormat> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        match Path::new(self.config_path()).extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(ConfigFormat::Json),
+            Some("yaml" | "yml") => anyhow::bail!("YAML configs are not supported yet, please convert {} to TOML or JSON", self.config_path()),
+            _ => Ok(ConfigFormat::Toml),
+        }
+    }
+}
 
-    let mut client = isahc::HttpClient::new()?;
-    let mut ip = get_current_ip(&mut client)?;
-    loop {
+/// Reads, completes and validates the config, so a broken file never replaces a working one.
+fn load_config(cli: &Cli) -> anyhow::Result<Config> {
+    read_config(cli).map_err(|e| DynsError::Config(format!("{:#}", e)).into())
+}
+
+fn read_config(cli: &Cli) -> anyhow::Result<Config> {
+    let path = cli.config_path();
+    let text = if path == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read the config from stdin")?
+    } else {
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read config file {}", path))?
+    };
+    let mut cfg: Config = match cli.config_format()? {
+        ConfigFormat::Toml => toml::from_str(&text)
+            .map_err(|e| parse_error(path, &text, e.line_col(), e))?,
+        ConfigFormat::Json => serde_json::from_str(&text)
+            .map_err(|e| parse_error(path, &text, Some((e.line().saturating_sub(1), e.column().saturating_sub(1))), e))?,
+    };
+    select_profile(&mut cfg, cli.profile.as_deref())?;
+    if let Some(base) = &cli.base_dir {
+        cfg.absolutize_paths(base);
+    }
+
+    match &cfg.zones_dir {
+        Some(dir) => load_zones_dir(Path::new(dir), cli.config_path(), &mut cfg.zones)?,
+        None if Path::new(paths::zones_dir()).is_dir() => load_zones_dir(Path::new(paths::zones_dir()), cli.config_path(), &mut cfg.zones)?,
+        None => (),
+    }
+    for zone in &mut cfg.zones {
+        zone.apply_defaults();
+    }
+    if let Some(email) = &cli.email {
+        cfg.email = Some(email.clone());
+    }
+    if let Some(auth_key) = &cli.auth_key {
+        cfg.auth_key = Some(auth_key.clone());
+    }
+    match (&cli.token, &cli.email, &cli.auth_key) {
+        (Some(token), _, _) => cfg.authorization = Some(token.clone()),
+        // Otherwise a token from the config would win over the credentials given on the command line.
+        (None, Some(_), Some(_)) => cfg.authorization = None,
+        _ => (),
+    }
+    if let Some(proxy) = cli.override_proxy {
+        for zone in cfg.zones.iter_mut().filter(|zone| zone.rfc2136.is_none()) {
+            for record in zone.records.iter_mut().filter(|record| record.record_type().is_proxiable()) {
+                record.proxy = Some(proxy);
+            }
+        }
+    }
+    cfg.validate()?;
+    select_allowed_types(&mut cfg)?;
+    select_targets(&mut cfg, cli.zone.as_deref(), cli.record.as_deref())?;
+    select_tags(&mut cfg, &cli.only_tags, cli.all_tags)?;
+    Ok(cfg)
+}
+
+/// Makes `path` relative to `base` if it's relative, leaving `-` (stdin) and empty (disabled) alone.
+fn absolutize(base: &Path, path: &mut String) {
+    if path != "-" && !path.is_empty() && Path::new(path.as_str()).is_relative() {
+        *path = base.join(path.as_str()).display().to_string();
+    }
+}
+
+/// Drops the records whose type isn't in `allowed_record_types`, remembering them for the warning.
+fn select_allowed_types(cfg: &mut Config) -> anyhow::Result<()> {
+    if cfg.allowed_record_types.is_empty() {
+        return Ok(());
+    }
+    let allowed = cfg.allowed_record_types.clone();
+    for zone in &mut cfg.zones {
+        let zone_name = zone.name.clone();
+        zone.records.retain(|record| {
+            let keep = allowed.contains(&record.record_type());
+            if !keep {
+                cfg.disallowed_records.push(format!("{} {} in zone {}", record.record_type(), record.name, zone_name));
+            }
+            keep
+        });
+    }
+    cfg.zones.retain(|zone| !zone.records.is_empty());
+    if cfg.zones.is_empty() {
+        let allowed: Vec<_> = allowed.iter().map(ToString::to_string).collect();
+        anyhow::bail!("No record has one of allowed_record_types ({})", allowed.join(", "));
+    }
+    Ok(())
+}
+
+/// Drops the records without any of `tags`, or without all of them if `all` is set. No tags keep everything.
+fn select_tags(cfg: &mut Config, tags: &[String], all: bool) -> anyhow::Result<()> {
+    if tags.is_empty() {
+        return Ok(());
+    }
+    for zone in &mut cfg.zones {
+        zone.records.retain(|record| match all {
+            true => tags.iter().all(|tag| record.tags.contains(tag)),
+            false => tags.iter().any(|tag| record.tags.contains(tag)),
+        });
+    }
+    cfg.zones.retain(|zone| !zone.records.is_empty());
+    if cfg.zones.is_empty() {
+        anyhow::bail!("No record is tagged with {} of {}", if all { "all" } else { "any" }, tags.join(", "));
+    }
+    Ok(())
+}
+
+/// Drops the zones and records not matched by `--zone` and `--record`.
+fn select_targets(cfg: &mut Config, zone: Option<&str>, record: Option<&str>) -> anyhow::Result<()> {
+    if let Some(wanted) = zone {
+        cfg.zones.retain(|zone| zone.zone_id == wanted || zone.name == wanted);
+        if cfg.zones.is_empty() {
+            anyhow::bail!("No zone with zone_id or name {} is configured", wanted);
+        }
+    }
+    if let Some(wanted) = record {
+        for zone in &mut cfg.zones {
+            zone.records.retain(|record| record.name == wanted);
+        }
+        cfg.zones.retain(|zone| !zone.records.is_empty());
+        if cfg.zones.is_empty() {
+            anyhow::bail!("No record named {} is configured{}", wanted, zone.map(|zone| format!(" in zone {}", zone)).unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
+/// Moves the zones of the selected profile, or of all of them if none is, into `cfg.zones`.
+fn select_profile(cfg: &mut Config, profile: Option<&str>) -> anyhow::Result<()> {
+    let profiles = std::mem::take(&mut cfg.profiles);
+    match profile.or(cfg.default_profile.as_deref()) {
+        Some(name) => {
+            let Some(profile) = profiles.get(name) else {
+                let known: Vec<_> = profiles.keys().map(String::as_str).collect();
+                anyhow::bail!("No profile named {} (known profiles: {})", name, known.join(", "));
+            };
+            log::debug!("Using profile {}", name);
+            cfg.zones.extend(profile.zones.iter().cloned());
+        }
+        None => cfg.zones.extend(profiles.into_values().flat_map(|profile| profile.zones)),
+    }
+    Ok(())
+}
+
+/// Wraps a config parse error with the file path and, when the position is known, an excerpt
+/// pointing at the offending spot. `line_col` is zero-based.
+fn parse_error(path: &str, text: &str, line_col: Option<(usize, usize)>, error: impl std::fmt::Display) -> anyhow::Error {
+    let mut message = format!("Failed to parse {}: {}", path, error);
+    if let Some((line, col)) = line_col {
+        let first = line.saturating_sub(2);
+        for (n, content) in text.lines().enumerate().skip(first).take(line - first + 1) {
+            message += &format!("\n{:>5} | {}", n + 1, content);
+        }
+        message += &format!("\n      | {}^", " ".repeat(col));
+    }
+    anyhow::anyhow!(message)
+}
+
+/// Appends the zones defined in `dir/*.toml` to `zones`, refusing zones defined more than once.
+fn load_zones_dir(dir: &Path, config_path: &str, zones: &mut Vec<ZoneConfig>) -> anyhow::Result<()> {
+    let mut sources: HashMap<String, PathBuf> = zones.iter()
+        .map(|zone| (zone.zone_id.clone(), PathBuf::from(config_path)))
+        .collect();
+    let mut files: Vec<_> = std::fs::read_dir(dir).with_context(|| format!("Failed to read zones directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    files.retain(|path| path.extension().is_some_and(|ext| ext == "toml"));
+    files.sort();
+    for path in files {
+        let text = std::fs::read_to_string(&path).with_context(|| format!("Failed to read zone file {}", path.display()))?;
+        let zone: ZoneConfig = toml::from_str(&text)
+            .map_err(|e| parse_error(&path.display().to_string(), &text, e.line_col(), e))?;
+        if let Some(other) = sources.get(&zone.zone_id) {
+            anyhow::bail!("Zone {} is defined in both {} and {}", zone.zone_id, other.display(), path.display());
+        }
+        sources.insert(zone.zone_id.clone(), path);
+        zones.push(zone);
+    }
+    Ok(())
+}
+
+/// Remembers the outcome of an update pass in the state file, if there is one.
+fn save_state(cfg: &Config, state: &mut State, addresses: Addresses, report: &PassReport) {
+    let Some(path) = cfg.state_path() else { return };
+    state.record_pass(addresses.ip, addresses.ipv6.map(|ipv6| ipv6.addr));
+    for record in &report.records {
+        state.record(&record.zone, &record.name, &record.outcome);
+    }
+    // Failing once usually means failing every pass, e.g. when run_as may not write there.
+    match state.save(path) {
+        Ok(()) => dedup::clear("save state"),
+        Err(e) => dedup::log(log::Level::Warn, "save state", format!("Failed to save state to {}: {}", path.display(), e)),
+    }
+}
+
+/// The file errors are logged to when there are no `log_outputs`, unless it's disabled.
+fn log_file<'a>(cli: &'a Cli, cfg: &'a Config) -> Option<&'a Path> {
+    let log_file = cli.log_file.as_ref().or(cfg.log_file.as_ref()).map_or(paths::log_file(), String::as_str);
+    // An empty path disables the file logger too, which is handy in containers.
+    Some(Path::new(log_file)).filter(|path| !cli.no_log_file && !path.as_os_str().is_empty())
+}
+
+fn http_client(cli: &Cli, cfg: &Config) -> anyhow::Result<Http> {
+    let client = Http::new(cfg.max_concurrent_requests.unwrap_or(4))?;
+    Ok(match (&cli.record_http, &cli.replay_http) {
+        (Some(path), _) => client.record_to(path),
+        (None, Some(path)) => client.replay_from(path).with_context(|| format!("Failed to load HTTP cassette {}", path))?,
+        (None, None) => client,
+    })
+}
+
+/// Writes every DNS record of every configured Cloudflare zone to `output` as JSON. Records are kept
+/// exactly as Cloudflare lists them, including the ones dyns doesn't manage.
+fn backup(client: &Http, cfg: &Config, output: &Path) -> anyhow::Result<()> {
+    let mut zones = Vec::new();
+    for zone in &cfg.zones {
+        if zone.rfc2136.is_some() {
+            log::warn!("Not backing up {}: RFC 2136 zones can't be listed", zone.name);
+            continue;
+        }
+        let records: Vec<serde_json::Value> = list_all_dns_records(client, cfg, &zone.zone_id)
+            .with_context(|| format!("Failed to list the records of zone {}", zone.name))?;
+        log::info!("Backing up {} records of zone {}", records.len(), zone.name);
+        zones.push(serde_json::json!({ "name": zone.name, "zone_id": zone.zone_id, "records": records }));
+    }
+    let backup = serde_json::json!({
+        "created": time::OffsetDateTime::now_utc().format(&time::format_description::well_known::Rfc3339)?,
+        "zones": zones,
+    });
+    std::fs::write(output, serde_json::to_string_pretty(&backup)? + "\n").with_context(|| format!("Failed to write backup to {}", output.display()))?;
+    log::info!("Wrote backup to {}", output.display());
+    Ok(())
+}
+
+/// Prints the outcome of one `dyns doctor` check, with `hint` if it failed, and counts failures.
+fn doctor_check<T>(failed: &mut usize, what: &str, result: anyhow::Result<T>, detail: impl FnOnce(&T) -> String, hint: &str) -> Option<T> {
+    match result {
+        Ok(value) => {
+            println!("[ OK ] {}: {}", what, detail(&value));
+            Some(value)
+        }
+        Err(e) => {
+            *failed += 1;
+            println!("[FAIL] {}: {:#}", what, e);
+            let hint = match error::kind(&e) {
+                Some(DynsError::Network) => "No IP provider could be reached: check the network connection, firewall and proxy settings",
+                Some(DynsError::Auth { .. }) => "Check authorization (an API token with the Zone.DNS Edit permission for every zone), or email and auth_key",
+                Some(DynsError::Unavailable { .. }) => "Cloudflare is having issues of its own, try again later",
+                Some(e) if e.is_network() => "The server could not be reached: check the network connection, firewall and proxy settings",
+                _ => hint,
+            };
+            println!("       {}", hint);
+            None
+        }
+    }
+}
+
+/// Runs through what usually goes wrong when setting dyns up, failing if any check did.
+fn doctor(cli: &Cli) -> anyhow::Result<()> {
+    let mut failed = 0;
+    let path = cli.config_path();
+    let Some(cfg) = doctor_check(&mut failed, "config", load_config(cli),
+                                 |_| format!("{} is valid", path),
+                                 "Fix the config file, or point -c at the right one") else {
+        anyhow::bail!("The config is unusable, skipping the other checks");
+    };
+
+    let log_files: Vec<&Path> = match &cfg.log_outputs {
+        Some(outputs) => outputs.iter().filter(|output| output.target == LogTarget::File).filter_map(|output| output.path.as_deref().map(Path::new)).collect(),
+        None => log_file(cli, &cfg).into_iter().collect(),
+    };
+    for log_file in log_files {
+        doctor_check(&mut failed, &format!("log file {}", log_file.display()), DatedFile::check_writable(log_file).map_err(anyhow::Error::from),
+                     |path| format!("{} is writable", path.display()),
+                     "Create its directory and make it writable by the user dyns runs as, or pass --no-log-file");
+    }
+
+    let client = http_client(cli, &cfg)?;
+    doctor_check(&mut failed, "IP detection", detect_addresses_once(&client, &cfg), |addresses| format!("detected {}", addresses),
+                 "Check ip_providers, or whether allowed_cidrs and on_cgnat reject the detected address");
+
+    if !cfg.zones.iter().any(|zone| zone.rfc2136.is_none()) {
+        println!("[SKIP] credentials: no Cloudflare zones are configured");
+    } else if doctor_check(&mut failed, "credentials", verify_credentials(&client, &cfg).map_err(anyhow::Error::from), |_| "Cloudflare accepted them".to_string(),
+                           "Check authorization, or email and auth_key").is_some() {
         for zone in &cfg.zones {
+            if zone.rfc2136.is_some() {
+                println!("[SKIP] zone {}: RFC 2136 zones can't be listed", zone.name);
+                continue;
+            }
+            let Some(listing) = doctor_check(&mut failed, &format!("zone {}", zone.name), list_all_dns_records::<RecordInfo>(&client, &cfg, &zone.zone_id).map_err(anyhow::Error::from),
+                                             |listing| format!("{} has {} records", zone.zone_id, listing.len()),
+                                             "Check zone_id, it's shown on the zone's overview page in the Cloudflare dashboard") else { continue };
             for record in &zone.records {
-                if let Err(e) = update_record(&mut client, &cfg, &zone.zone_id, record, &ip) {
-                    log::error!("An error happened while updating record {} of zone {}: {}", zone.name, record.name, e);
-                    return Err(e);
+                let wanted_type = record.record_type().to_string();
+                let same_name: Vec<_> = listing.iter().filter(|info| info.name == record.name).collect();
+                // Record sets are reconciled, which creates whatever is missing.
+                let creates = record.creates_missing() || record.contents.is_some() || record.contents_command.is_some();
+                let found = match (same_name.iter().any(|info| info.type_ == wanted_type), same_name.first()) {
+                    (true, _) => Ok("exists".to_string()),
+                    (false, Some(info)) if creates => Ok(format!("only exists as {}, will be created next to it", info.type_)),
+                    (false, None) if creates => Ok("doesn't exist yet, will be created".to_string()),
+                    (false, Some(info)) => Err(anyhow::anyhow!("only exists as {}", info.type_)),
+                    (false, None) => Err(anyhow::anyhow!("doesn't exist in the zone")),
+                };
+                let hint = match same_name.first() {
+                    Some(_) => "Make the configured type match the existing record, or set create_missing = true to add one next to it",
+                    None => "Create it in the Cloudflare dashboard, or set create_missing = true",
+                };
+                doctor_check(&mut failed, &format!("record {} {}", wanted_type, record.name), found, Clone::clone, hint);
+            }
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} checks failed", failed);
+    }
+    println!("Everything looks good");
+    Ok(())
+}
+
+/// Prints, for every record, what an update pass would change, without changing anything.
+fn dry_run(client: &Http, cfg: &Config, addresses: Addresses) -> anyhow::Result<()> {
+    let wan = detect_wan(client, cfg);
+    let mut zones: HashMap<&str, Vec<RecordInfo>> = HashMap::new();
+    for (zone, record) in cfg.ordered_records() {
+        let label = format!("{} {} ({})", record.record_type(), record.name, zone.name);
+        let Some((record, address)) = record.resolve(&zone.name, addresses, &wan) else {
+            println!("{}: no address known, would be skipped", label);
+            continue;
+        };
+        let record = &*record;
+        let content = record.content(&address);
+        if zone.rfc2136.is_some() {
+            println!("{}: would be set to {} over RFC 2136, which can't be compared beforehand", label, content);
+            continue;
+        }
+        let existing = match zones.entry(&zone.zone_id) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => entry.insert(list_all_dns_records::<RecordInfo>(client, cfg, &zone.zone_id)
+                .with_context(|| format!("Failed to list the records of zone {}", zone.name))?),
+        };
+        let wanted_type = record.record_type().to_string();
+        let same_name: Vec<_> = existing.iter().filter(|info| info.name == record.name).collect();
+        let same_type: Vec<_> = same_name.iter().copied().filter(|info| info.type_ == wanted_type).collect();
+        if let Some(contents) = record.desired_contents(&content)? {
+            let missing: Vec<_> = contents.iter().filter(|content| !same_type.iter().any(|info| &info.content == *content)).collect();
+            let extra: Vec<_> = same_type.iter().filter(|info| !contents.contains(&info.content)).map(|info| &info.content).collect();
+            match (missing.is_empty(), extra.is_empty()) {
+                (true, true) => println!("{}: up to date", label),
+                _ => println!("{}: would add {:?} and remove {:?}", label, missing, extra),
+            }
+            continue;
+        }
+        if same_type.is_empty() {
+            match same_name.first() {
+                Some(info) if record.creates_missing() => println!("{}: only exists as {}, a {} record would be created next to it pointing to {}",
+                                                                   label, info.type_, wanted_type, content),
+                Some(info) => println!("{}: only exists as {}, the update would fail without touching it", label, info.type_),
+                None if record.creates_missing() => println!("{}: doesn't exist, would be created pointing to {}", label, content),
+                None => println!("{}: doesn't exist, the update would fail", label),
+            }
+            continue;
+        }
+        for info in same_type {
+            let differences = record_diff(cfg, record, info, &content);
+            match differences.is_empty() {
+                true => println!("{}: up to date", label),
+                false => println!("{}: {}", label, differences.join(", ")),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How `info` differs from what [`write_record`] would make of it.
+fn record_diff(cfg: &Config, record: &Record, info: &RecordInfo, content: &str) -> Vec<String> {
+    let replaces = matches!(cfg.update_method, UpdateMethod::Put);
+    let mut differences = Vec::new();
+    // Cloudflare derives the content of SRV records from `data`, which isn't listed.
+    if record.data.is_none() && info.content != content {
+        differences.push(format!("content {} -> {}", info.content, content));
+    }
+    if (replaces || cfg.syncs(SyncField::Proxied)) && info.proxied != record.proxied() {
+        differences.push(format!("proxied {} -> {}", info.proxied, record.proxied()));
+    }
+    let ttl = if replaces { Some(record.ttl.unwrap_or(1)) } else { record.ttl.filter(|_| cfg.syncs(SyncField::Ttl)) };
+    match (info.ttl, ttl) {
+        (Some(current), Some(ttl)) if current != ttl => differences.push(format!("ttl {} -> {}", current, ttl)),
+        _ => (),
+    }
+    if (replaces || cfg.syncs(SyncField::Comment)) && info.comment.as_deref().unwrap_or_default() != record.comment.as_deref().unwrap_or_default() {
+        differences.push(format!("comment {:?} -> {:?}", info.comment.as_deref().unwrap_or_default(), record.comment.as_deref().unwrap_or_default()));
+    }
+    if (replaces || cfg.syncs(SyncField::Tags)) && info.tags != record.cloudflare_tags {
+        differences.push(format!("tags {:?} -> {:?}", info.tags, record.cloudflare_tags));
+    }
+    differences
+}
+
+/// How many times in a row a pass is redone because the IP changed while it ran.
+const RECONFIRM_RESTARTS: u32 = 3;
+
+/// Detects the addresses again after a pass, returning them if they changed while it ran.
+/// A failed detection counts as unchanged; the next poll will sort it out.
+fn reconfirm_addresses(client: &Http, cfg: &Config, addresses: Addresses) -> Option<Addresses> {
+    let mut new = detect_addresses_once(client, cfg).ok()?;
+    new.ipv6 = new.ipv6.or(addresses.ipv6);
+    (new != addresses).then(|| {
+        log::info!("IP changed to {} during the update pass, updating again", new);
+        emit_ip_changes(client, cfg, addresses, new);
+        new
+    })
+}
+
+/// Sends `ip_changed` for each address family whose address went from `old` to something else in `new`.
+fn emit_ip_changes(client: &Http, cfg: &Config, old: Addresses, new: Addresses) {
+    for (old_ip, new_ip) in [(old.ip, new.ip), (old.ipv6.map(|v6| IpAddr::V6(v6.addr)), new.ipv6.map(|v6| IpAddr::V6(v6.addr)))] {
+        match (old_ip, new_ip) {
+            (Some(old_ip), Some(new_ip)) if old_ip != new_ip => emit(client, cfg, Event::IpChanged { old_ip, new_ip }),
+            _ => (),
+        }
+    }
+}
+
+/// Used by `--once` and `--no-wait`: updates everything once and reports whether it all worked.
+/// The pass is redone if the IP changed meanwhile when `reconfirm` is set.
+fn single_pass(client: &Http, cfg: &Config, mut addresses: Addresses, history: &mut History, state: &mut State, reconfirm: bool) -> anyhow::Result<()> {
+    let mut restarts = 0;
+    let report = loop {
+        let new_ip = addresses.primary().context("Could not detect any address to publish")?;
+        let report = update_all(client, cfg, addresses, &detect_wan(client, cfg), history, None);
+        health::record_pass(report.complete);
+        metrics::record_pass(report.complete);
+        save_state(cfg, state, addresses, &report);
+        emit(client, cfg, Event::RecordsUpdated { old_ip: None, new_ip, records: &report.records });
+        let again = (reconfirm && report.done() && restarts < RECONFIRM_RESTARTS).then(|| reconfirm_addresses(client, cfg, addresses));
+        match again.flatten() {
+            Some(new) => {
+                addresses = new;
+                restarts += 1;
+            }
+            None => break report,
+        }
+    };
+    if report.complete && !report.done() {
+        anyhow::bail!("Some records were written too recently to update them again, see min_update_interval_secs");
+    }
+    if !report.complete {
+        let failed = report.records.iter().filter(|record| matches!(record.outcome, Outcome::Failed(_))).count();
+        anyhow::bail!("{} of {} records could not be updated", failed, report.records.len());
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut cli = Cli::parse();
+    cli.config = cli.config.or_else(|| std::env::var("DYNS_CONFIG").ok());
+    cli.log_file = cli.log_file.or_else(|| std::env::var("DYNS_LOG_FILE").ok());
+    if cli.override_proxy.is_none() {
+        if let Ok(value) = std::env::var("DYNS_OVERRIDE_PROXY") {
+            cli.override_proxy = Some(value.parse().with_context(|| format!("DYNS_OVERRIDE_PROXY must be true or false, not {:?}", value))?);
+        }
+    }
+    cli.docker |= std::env::var_os("DYNS_DOCKER").is_some_and(|value| !value.is_empty() && value != "0");
+    if cli.docker {
+        cli.config = cli.config.or_else(|| Some("-".to_string()));
+        cli.no_log_file = true;
+    }
+
+    // Daemonizing changes the working directory to /, so relative paths would break after it, and
+    // on every reload.
+    if cli.daemonize {
+        cli.pin_paths()?;
+    }
+
+    // Hooks calling this shouldn't need to be able to read the config.
+    if let Some(Command::Trigger) = cli.command {
+        #[cfg(unix)]
+        {
+            let pid = daemon::trigger_update(Path::new(cli.pid_file.as_deref().unwrap_or(PID_FILE)))?;
+            println!("Asked dyns (PID {}) to update now", pid);
+            return Ok(());
+        }
+        #[cfg(not(unix))]
+        anyhow::bail!("`dyns trigger` is only supported on Unix");
+    }
+
+    // Reports a broken config itself, and logs nothing so its output stays readable.
+    if let Some(Command::Doctor) = cli.command {
+        return doctor(&cli);
+    }
+
+    let mut cfg = load_config(&cli)?;
+    if let Some(Command::Stats) = cli.command {
+        let path = cfg.state_path().context("state_file is disabled, there are no stats to show")?;
+        let state = State::load(path).with_context(|| format!("Failed to read state file {}", path.display()))?;
+        state.print();
+        return Ok(());
+    }
+
+    let color = !cli.no_color && if cli.docker { std::io::stdout().is_terminal() } else { std::io::stderr().is_terminal() };
+    let log_file = log_file(&cli, &cfg);
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => log::LevelFilter::Warn,
+        (_, true) => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Info,
+    };
+    let log_outputs = match &cfg.log_outputs {
+        Some(outputs) => outputs.clone(),
+        None => {
+            let target = if cli.docker { LogTarget::Stdout } else { LogTarget::Console };
+            let console = LogOutput { target, path: None, level: level.into(), format: LogFormat::Text, color };
+            let file = log_file.map(|path| LogOutput {
+                target: LogTarget::File,
+                path: Some(path.display().to_string()),
+                level: LogLevel::Error,
+                format: LogFormat::Text,
+                color: false,
+            });
+            std::iter::once(console).chain(file).collect()
+        }
+    };
+    init_logger(&log_outputs);
+    if let Some(Command::Backup { output }) = &cli.command {
+        return backup(&http_client(&cli, &cfg)?, &cfg, output);
+    }
+    if cli.test_webhook {
+        let url = cfg.webhook_url.as_ref().context("No webhook_url configured")?;
+        let status = send_webhook(&http_client(&cli, &cfg)?, url, &Event::Test)?;
+        log::info!("Webhook {} accepted the test event with {}", url, status);
+        return Ok(());
+    }
+    cfg.warn_about_settings();
+    cfg.log_startup_summary(&log_outputs);
+    if let Some(proxy) = cli.override_proxy {
+        log::warn!("Proxy override in effect: every proxiable record is published with proxied = {}, whatever the config says", proxy);
+    }
+    if cli.dry_run {
+        let client = http_client(&cli, &cfg)?;
+        return dry_run(&client, &cfg, detect_addresses_once(&client, &cfg)?);
+    }
+
+    if cli.daemonize {
+        #[cfg(unix)]
+        daemon::daemonize()?;
+        #[cfg(not(unix))]
+        anyhow::bail!("--daemonize is only supported on Unix");
+    }
+    // Written after forking, so it holds the daemon's PID; removed again whenever dyns stops.
+    #[cfg(unix)]
+    let pid_file = cli.pid_file.as_deref().or(cli.daemonize.then_some(PID_FILE))
+        .map(|path| daemon::PidFile::create(Path::new(path)))
+        .transpose()?;
+    // Bound after forking, while still privileged, but only served once privileges are dropped:
+    // drop_privileges may only run while there is a single thread.
+    #[cfg(unix)]
+    let event_socket = cfg.event_socket.as_deref()
+        .map(|path| socket::bind(Path::new(path)).with_context(|| format!("Failed to listen on event socket {}", path)))
+        .transpose()?;
+    let health_listener = cfg.health_listen.as_deref()
+        .map(|address| std::net::TcpListener::bind(address).with_context(|| format!("Failed to listen for health checks on {}", address)))
+        .transpose()?;
+    #[cfg(unix)]
+    if let Some(run_as) = &cfg.run_as {
+        daemon::drop_privileges(run_as)?;
+        for path in log_outputs.iter().filter(|output| output.target == LogTarget::File).filter_map(|output| output.path.as_deref()) {
+            if DatedFile::is_dated(Path::new(path)) && !daemon::can_create_in(Path::new(path)) {
+                log::warn!("{} can't create files next to {}, so logging goes on in the current file when the date in its name changes", run_as.user, path);
+            }
+        }
+    }
+    #[cfg(unix)]
+    if let Some(listener) = event_socket {
+        socket::serve(listener);
+    }
+    let mut history = History::new(cfg.history_size.unwrap_or(10));
+    if let Some(listener) = health_listener {
+        health::serve(listener, cfg.health_thresholds.clone(), history.clone());
+    }
+
+    signals::install();
+    let mut state = match cfg.state_path().map(State::load) {
+        Some(Ok(state)) => state,
+        Some(Err(e)) => {
+            log::warn!("Failed to read the state file, starting afresh: {}", e);
+            State::default()
+        }
+        None => State::default(),
+    };
+    if cfg.watch_config && cli.config_path() == "-" {
+        log::warn!("watch_config has no effect when the config is read from stdin");
+    }
+    let mut watcher = (cfg.watch_config && cli.config_path() != "-").then(|| FileWatcher::new(cli.config_path()));
+    let client = http_client(&cli, &cfg)?;
+    if let Some(interface) = cfg.wait_for_interface.as_ref().filter(|_| !cli.no_wait) {
+        wait_for_interface(interface, Duration::from_secs(cfg.wait_for_interface_timeout_secs.unwrap_or(120)));
+    }
+    if cfg.zones.iter().any(|zone| zone.rfc2136.is_none()) {
+        match verify_credentials(&client, &cfg) {
+            Ok(()) => log::info!("Cloudflare accepted the credentials"),
+            Err(e @ DynsError::Auth { .. }) => {
+                log::error!("{:#}", e);
+                // exit skips destructors.
+                #[cfg(unix)]
+                drop(pid_file);
+                std::process::exit(EXIT_BAD_CREDENTIALS);
+            }
+            // Possibly just the network not being up yet; the update passes will tell.
+            Err(e) => log::warn!("Could not verify the credentials with Cloudflare: {:#}", e),
+        }
+    }
+    if cli.no_wait {
+        return single_pass(&client, &cfg, detect_addresses_once(&client, &cfg)?, &mut history, &mut state, false);
+    }
+    if let Some(delay) = cfg.initial_delay_secs.filter(|delay| *delay > 0) {
+        log::info!("Waiting {}s before the first update", delay);
+        std::thread::sleep(Duration::from_secs(delay));
+    }
+    let mut addresses = detect_addresses(&client, &cfg, &history);
+    let mut wan = detect_wan(&client, &cfg);
+    if cli.once {
+        if let Some(window) = cfg.update_window.as_ref().filter(|window| !window.is_open()) {
+            log::info!("Outside the update window ({}-{}), not publishing {}", window.start, window.end, addresses);
+            return Ok(());
+        }
+        if !cfg.is_active() {
+            log::info!("{} doesn't exist, not publishing {}", cfg.active_when_file_exists.as_deref().unwrap_or_default(), addresses);
+            return Ok(());
+        }
+        return single_pass(&client, &cfg, addresses, &mut history, &mut state, true);
+    }
+    let started = Instant::now();
+    let mut pending = true;
+    // Set while only records held back by their cooldown still need the current addresses.
+    let mut held: Option<Vec<(String, String, RecordType)>> = None;
+    let mut deferred = false;
+    let mut standby = false;
+    let mut outage_delay = None;
+    let mut published = None;
+    let mut restarts = 0;
+    loop {
+        if cfg.runtime_exceeded(started) {
+            log::info!("Reached max_runtime_secs, exiting");
+            return Ok(());
+        }
+        let active = cfg.is_active();
+        if active == standby {
+            let path = cfg.active_when_file_exists.as_deref().unwrap_or_default();
+            match active {
+                true => log::info!("{} exists, taking over updates", path),
+                false => log::info!("{} doesn't exist, standing by without updating records", path),
+            }
+            standby = !active;
+        }
+        if let Some(new_ip) = addresses.primary().filter(|_| pending && active) {
+            match cfg.update_window.as_ref().filter(|window| !window.is_open()) {
+                Some(window) if !deferred => {
+                    log::info!("Outside the update window ({}-{}), holding off publishing {}", window.start, window.end, addresses);
+                    deferred = true;
+                }
+                Some(_) => (),
+                None => {
+                    deferred = false;
+                    let report = update_all(&client, &cfg, addresses, &wan, &mut history, held.as_deref());
+                    health::record_pass(report.complete);
+                    metrics::record_pass(report.complete);
+                    save_state(&cfg, &mut state, addresses, &report);
+                    emit(&client, &cfg, Event::RecordsUpdated { old_ip: published, new_ip, records: &report.records });
+                    pending = !report.done();
+                    held = (report.complete && pending).then(|| report.held());
+                    if report.done() {
+                        published = Some(new_ip);
+                        if let Some(new) = (restarts < RECONFIRM_RESTARTS).then(|| reconfirm_addresses(&client, &cfg, addresses)).flatten() {
+                            addresses = new;
+                            pending = true;
+                            restarts += 1;
+                            continue;
+                        }
+                    }
+                    restarts = 0;
+                    // Retry sooner than a whole poll interval, doubling the delay while the outage lasts.
+                    outage_delay = report.outage.then(|| outage_delay.map_or(OUTAGE_RETRY_BASE, |delay| delay * 2).min(cfg.poll_interval()));
                 }
             }
         }
-        loop {
-            std::thread::sleep(Duration::from_secs(5*60));
-            let new_ip = get_current_ip(&mut client)?;
-            if new_ip != ip {
-                ip = new_ip;
-                break;
+        let forced = match sleep(outage_delay.unwrap_or(cfg.poll_interval()), &history, watcher.as_mut()) {
+            Wake::Timeout => false,
+            Wake::UpdateRequested => {
+                log::info!("Received SIGUSR2, updating all records now");
+                true
+            }
+            Wake::ClockJumped(secs) if secs > 0 => {
+                log::info!("Clock jumped ahead by {}s, probably resumed from suspend; checking the IP now", secs);
+                true
+            }
+            Wake::ClockJumped(secs) => {
+                log::info!("Clock was set back by {}s, checking the IP now", -secs);
+                true
             }
+            Wake::ConfigChanged => {
+                match load_config(&cli) {
+                    Ok(new_cfg) => {
+                        log::info!("Config file changed, reloaded it");
+                        new_cfg.warn_about_settings();
+                        cfg = new_cfg;
+                        pending = true;
+                        held = None;
+                    }
+                    Err(e) => {
+                        log::error!("Config file changed but is invalid, keeping the old one: {:#}", e);
+                        emit(&client, &cfg, Event::Error { message: &format!("Config file changed but is invalid: {:#}", e) });
+                    }
+                }
+                continue;
+            }
+        };
+        let mut new = detect_addresses(&client, &cfg, &history);
+        // Keep the last known IPv6 address while there's none, e.g. during a renumbering.
+        new.ipv6 = new.ipv6.or(addresses.ipv6);
+        emit_ip_changes(&client, &cfg, addresses, new);
+        let new_wan = detect_wan(&client, &cfg);
+        if new_wan != wan {
+            log::info!("The addresses of the WAN uplinks changed");
+            wan = new_wan;
+            pending = true;
+            held = None;
+        }
+        if new != addresses {
+            addresses = new;
+            pending = true;
+            held = None;
+        } else if forced {
+            pending = true;
+            held = None;
+        } else if !pending {
             log::info!("IP hasn't changed, sleeping...");
         }
     }
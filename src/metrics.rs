@@ -0,0 +1,84 @@
+//! Counters exposed at `/metrics` in the OpenMetrics text format, for strict scrapers.
+
+use std::{collections::BTreeMap, sync::Mutex, time::SystemTime};
+
+#[derive(Default)]
+struct Counter {
+    value: u64,
+    /// Labels and time of the last increment, sent as the exemplar.
+    exemplar: Option<(String, f64)>,
+}
+
+#[derive(Default)]
+struct Metrics {
+    passes: BTreeMap<&'static str, u64>,
+    last_pass: Option<f64>,
+    /// Keyed by zone, record and result.
+    updates: BTreeMap<(String, String, &'static str), Counter>,
+}
+
+static METRICS: Mutex<Option<Metrics>> = Mutex::new(None);
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+fn result(ok: bool) -> &'static str {
+    if ok { "success" } else { "failure" }
+}
+
+pub fn record_pass(complete: bool) {
+    let mut metrics = METRICS.lock().unwrap();
+    let metrics = metrics.get_or_insert_with(Metrics::default);
+    *metrics.passes.entry(result(complete)).or_default() += 1;
+    metrics.last_pass = Some(now());
+}
+
+/// Counts one attempt to point `record` of `zone` at `address`; `result` is "success", "failure" or
+/// "held" when its cooldown held it back.
+pub fn record_update(zone: &str, record: &str, result: &'static str, address: &str) {
+    let mut metrics = METRICS.lock().unwrap();
+    let counter = metrics.get_or_insert_with(Metrics::default).updates
+        .entry((zone.to_string(), record.to_string(), result))
+        .or_default();
+    counter.value += 1;
+    counter.exemplar = Some((format!("address=\"{}\"", escape(address)), now()));
+}
+
+/// Label values may not contain raw backslashes, quotes or newlines.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// The whole exposition, ending with `# EOF` as OpenMetrics requires.
+pub fn render() -> String {
+    let metrics = METRICS.lock().unwrap();
+    let empty = Metrics::default();
+    let metrics = metrics.as_ref().unwrap_or(&empty);
+    let mut out = String::new();
+    out += "# TYPE dyns_build_info gauge\n";
+    out += "# HELP dyns_build_info Version of the running dyns.\n";
+    out += &format!("dyns_build_info{{version=\"{}\",commit=\"{}\"}} 1\n", env!("CARGO_PKG_VERSION"), escape(env!("VERGEN_GIT_SHA")));
+    out += "# TYPE dyns_passes counter\n";
+    out += "# HELP dyns_passes Update passes, by whether every record was updated.\n";
+    for result in ["success", "failure"] {
+        out += &format!("dyns_passes_total{{result=\"{}\"}} {}\n", result, metrics.passes.get(result).copied().unwrap_or(0));
+    }
+    out += "# TYPE dyns_last_pass_timestamp_seconds gauge\n";
+    out += "# UNIT dyns_last_pass_timestamp_seconds seconds\n";
+    out += "# HELP dyns_last_pass_timestamp_seconds When the last update pass finished.\n";
+    if let Some(at) = metrics.last_pass {
+        out += &format!("dyns_last_pass_timestamp_seconds {:.3}\n", at);
+    }
+    out += "# TYPE dyns_record_updates counter\n";
+    out += "# HELP dyns_record_updates Attempts to update a record, by result, with the last address as exemplar.\n";
+    for ((zone, record, result), counter) in &metrics.updates {
+        out += &format!("dyns_record_updates_total{{zone=\"{}\",record=\"{}\",result=\"{}\"}} {}", escape(zone), escape(record), result, counter.value);
+        if let Some((labels, at)) = &counter.exemplar {
+            out += &format!(" # {{{}}} 1 {:.3}", labels, at);
+        }
+        out.push('\n');
+    }
+    out.push_str("# EOF\n");
+    out
+}
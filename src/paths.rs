@@ -0,0 +1,36 @@
+//! Where dyns looks for its files by default: the usual places on Unix, `%PROGRAMDATA%\dyns` on
+//! Windows.
+
+use std::sync::OnceLock;
+
+/// `unix`, or `windows` inside `%PROGRAMDATA%\dyns`, worked out once per `path`.
+fn platform(path: &'static OnceLock<String>, unix: &str, windows: &str) -> &'static str {
+    path.get_or_init(|| {
+        if cfg!(windows) {
+            let base = std::env::var("PROGRAMDATA").unwrap_or_else(|_| r"C:\ProgramData".to_string());
+            format!(r"{}\dyns\{}", base, windows)
+        } else {
+            unix.to_string()
+        }
+    })
+}
+
+pub fn config_file() -> &'static str {
+    static PATH: OnceLock<String> = OnceLock::new();
+    platform(&PATH, "/etc/dyns.toml", "dyns.toml")
+}
+
+pub fn log_file() -> &'static str {
+    static PATH: OnceLock<String> = OnceLock::new();
+    platform(&PATH, "/var/log/dyns.log", "dyns.log")
+}
+
+pub fn zones_dir() -> &'static str {
+    static PATH: OnceLock<String> = OnceLock::new();
+    platform(&PATH, "/etc/dyns.zones.d", "zones.d")
+}
+
+pub fn state_file() -> &'static str {
+    static PATH: OnceLock<String> = OnceLock::new();
+    platform(&PATH, "/var/lib/dyns/state.json", "state.json")
+}
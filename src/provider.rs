@@ -0,0 +1,79 @@
+use std::{io::Write, process::{Command, Stdio}};
+
+use crate::{error::DynsError, Record, RecordType};
+
+/// Something that can point DNS records at an address.
+pub trait DnsProvider {
+    fn update_record(&self, record: &Record, ip: &str) -> Result<(), DynsError>;
+}
+
+/// Server and TSIG key for RFC 2136 dynamic updates.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Rfc2136Config {
+    /// Authoritative server accepting the updates.
+    pub server: String,
+    pub port: Option<u16>,
+    pub key_name: String,
+    /// Base64 encoded TSIG secret.
+    pub secret: String,
+    /// TSIG algorithm (defaults to hmac-sha256).
+    pub algorithm: Option<String>,
+}
+
+/// Sends signed UPDATE messages to a self-hosted server (BIND, Knot, ...) through `nsupdate`.
+pub struct Rfc2136Provider<'a> {
+    config: &'a Rfc2136Config,
+    zone: &'a str,
+}
+
+/// Records without an explicit TTL get this one, since RFC 2136 has no "automatic" TTL.
+const DEFAULT_TTL: u32 = 300;
+
+impl<'a> Rfc2136Provider<'a> {
+    pub fn new(config: &'a Rfc2136Config, zone: &'a str) -> Self {
+        Self { config, zone }
+    }
+
+    fn script(&self, record: &Record, contents: &[String]) -> String {
+        let name = record.name.trim_end_matches('.');
+        let kind = record.record_type();
+        let ttl = record.ttl.unwrap_or(DEFAULT_TTL);
+        let mut script = format!("server {} {}\n", self.config.server, self.config.port.unwrap_or(53));
+        script += &format!("zone {}\n", self.zone);
+        // Passing the key on stdin keeps the secret out of `ps`.
+        script += &format!("key {}:{} {}\n", self.config.algorithm.as_deref().unwrap_or("hmac-sha256"), self.config.key_name, self.config.secret);
+        script += &format!("update delete {}. {}\n", name, kind);
+        for content in contents {
+            script += &format!("update add {}. {} {} {}\n", name, ttl, kind, content);
+        }
+        script += "send\n";
+        script
+    }
+}
+
+impl DnsProvider for Rfc2136Provider<'_> {
+    fn update_record(&self, record: &Record, ip: &str) -> Result<(), DynsError> {
+        let contents = match (record.desired_contents(ip)?, &record.data) {
+            (Some(contents), _) => contents,
+            (None, Some(data)) => vec![format!("{} {} {} {}", data.priority, data.weight, data.port, data.target.replace("${ip}", ip))],
+            (None, None) => vec![ip.to_string()],
+        };
+        let contents: Vec<_> = match record.record_type() {
+            RecordType::Txt => contents.iter().map(|content| format!("\"{}\"", content)).collect(),
+            _ => contents,
+        };
+        let mut child = Command::new("nsupdate")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| DynsError::Command { what: "nsupdate".to_string(), reason: format!("{}, is it installed?", e) })?;
+        child.stdin.take().unwrap().write_all(self.script(record, &contents).as_bytes())?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(DynsError::Command { what: "nsupdate".to_string(), reason: String::from_utf8_lossy(&output.stderr).trim().to_string() });
+        }
+        log::info!("Successfully updated record {} to {}", record.name, ip);
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+//! Signal handlers only raise flags; the main loop polls them while sleeping.
+//!
+//! - SIGUSR1 logs a summary of recent update outcomes.
+//! - SIGUSR2 triggers an immediate update pass.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SUMMARY_REQUESTED: AtomicBool = AtomicBool::new(false);
+static UPDATE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGUSR1 => SUMMARY_REQUESTED.store(true, Ordering::SeqCst),
+        libc::SIGUSR2 => UPDATE_REQUESTED.store(true, Ordering::SeqCst),
+        _ => (),
+    }
+}
+
+pub fn install() {
+    #[cfg(unix)]
+    for signal in [libc::SIGUSR1, libc::SIGUSR2] {
+        unsafe {
+            libc::signal(signal, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+}
+
+/// Whether SIGUSR1 arrived since the last call.
+pub fn take_summary_request() -> bool {
+    SUMMARY_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+/// Whether SIGUSR2 arrived since the last call.
+pub fn take_update_request() -> bool {
+    UPDATE_REQUESTED.swap(false, Ordering::SeqCst)
+}
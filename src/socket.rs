@@ -0,0 +1,39 @@
+//! Streams events as newline-delimited JSON to local consumers connected to a Unix socket.
+
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+static CLIENTS: Mutex<Vec<UnixStream>> = Mutex::new(Vec::new());
+
+/// Binds `path`, replacing a socket left behind by a previous run. Nobody is accepted until [`serve`].
+pub fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Starts accepting connections on `listener`.
+pub fn serve(listener: UnixListener) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    // A consumer that stops reading shouldn't stall updates.
+                    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                    CLIENTS.lock().unwrap().push(stream);
+                }
+                Err(e) => log::warn!("Failed to accept an event socket connection: {}", e),
+            }
+        }
+    });
+}
+
+/// Sends `line` to every connected consumer, dropping those that went away.
+pub fn broadcast(line: &str) {
+    CLIENTS.lock().unwrap().retain_mut(|client| writeln!(client, "{}", line).is_ok());
+}
@@ -0,0 +1,118 @@
+//! Persists what dyns last published, so `dyns stats` can show it even while the daemon isn't running.
+
+use std::{io, net::{IpAddr, Ipv6Addr}, path::Path};
+
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::history::Outcome;
+
+/// How many errors are remembered per record.
+const RECENT_ERRORS: usize = 5;
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct State {
+    pub ip: Option<IpAddr>,
+    pub ipv6: Option<Ipv6Addr>,
+    /// When the last update pass ran, as RFC 3339.
+    pub last_pass: Option<String>,
+    #[serde(default)]
+    pub records: Vec<RecordState>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RecordState {
+    pub zone: String,
+    pub name: String,
+    pub last_updated: Option<String>,
+    /// Since when `min_update_interval_secs` has been holding back a newer address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held_since: Option<String>,
+    /// Most recent last.
+    #[serde(default)]
+    pub errors: Vec<RecordError>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RecordError {
+    pub at: String,
+    pub message: String,
+}
+
+fn now() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+impl State {
+    /// Reads the state at `path`, which is empty if dyns never ran before.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the state to `path` through a temporary file, so readers never see half of it.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(temporary, path)
+    }
+
+    pub fn record_pass(&mut self, ip: Option<IpAddr>, ipv6: Option<Ipv6Addr>) {
+        self.ip = ip;
+        self.ipv6 = ipv6;
+        self.last_pass = Some(now());
+    }
+
+    /// Remembers the outcome of updating one record.
+    pub fn record(&mut self, zone: &str, name: &str, outcome: &Outcome) {
+        let index = match self.records.iter().position(|record| record.zone == zone && record.name == name) {
+            Some(index) => index,
+            None => {
+                self.records.push(RecordState { zone: zone.to_string(), name: name.to_string(), last_updated: None, held_since: None, errors: Vec::new() });
+                self.records.len() - 1
+            }
+        };
+        let record = &mut self.records[index];
+        match outcome {
+            Outcome::Updated => {
+                record.last_updated = Some(now());
+                record.held_since = None;
+            }
+            Outcome::Held => {
+                record.held_since.get_or_insert_with(now);
+            }
+            Outcome::Failed(message) => {
+                if record.errors.len() == RECENT_ERRORS {
+                    record.errors.remove(0);
+                }
+                record.errors.push(RecordError { at: now(), message: message.to_string() });
+            }
+        }
+    }
+
+    /// Prints the state for humans.
+    pub fn print(&self) {
+        let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+        println!("IP:        {}", or_unknown(self.ip.map(|ip| ip.to_string())));
+        if let Some(ipv6) = self.ipv6 {
+            println!("IPv6:      {}", ipv6);
+        }
+        println!("Last pass: {}", or_unknown(self.last_pass.clone()));
+        for record in &self.records {
+            println!();
+            println!("{} ({})", record.name, record.zone);
+            println!("  last updated: {}", record.last_updated.as_deref().unwrap_or("never"));
+            if let Some(since) = &record.held_since {
+                println!("  held back since: {}", since);
+            }
+            for error in &record.errors {
+                println!("  error at {}: {}", error.at, error.message);
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use std::{path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+
+/// How long the file must stay untouched before it is considered written.
+/// Editors often truncate, write and rename in quick succession.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Notices changes to a file by polling its modification time.
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    changed_at: Option<Instant>,
+}
+
+impl FileWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = Self::modified(&path);
+        Self { path, modified, changed_at: None }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+
+    /// Returns `true` once per change, after the file has settled.
+    pub fn poll(&mut self) -> bool {
+        let modified = Self::modified(&self.path);
+        if modified != self.modified {
+            self.modified = modified;
+            self.changed_at = Some(Instant::now());
+            return false;
+        }
+        match self.changed_at {
+            Some(at) if modified.is_some() && at.elapsed() >= DEBOUNCE => {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
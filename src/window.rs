@@ -0,0 +1,79 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Sun,
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [Weekday::Sun, Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat];
+
+    fn previous(self) -> Weekday {
+        Self::ALL[(self as usize + 6) % 7]
+    }
+}
+
+/// Minutes since midnight, written as `"HH:MM"` in the config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(u32);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid time of day {:?}, expected HH:MM", value);
+        let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+        let hours: u32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay(hours * 60 + minutes))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+/// Local time of day during which records may be changed. An `end` before `start`
+/// wraps past midnight, and such a window belongs to the day it starts on.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct UpdateWindow {
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+    /// Days the window opens on; empty means every day.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+}
+
+impl UpdateWindow {
+    pub fn is_open(&self) -> bool {
+        let (day, now) = local_now();
+        self.contains(day, now)
+    }
+
+    fn contains(&self, day: Weekday, now: TimeOfDay) -> bool {
+        let opens_on = |day| self.days.is_empty() || self.days.contains(&day);
+        if self.start <= self.end {
+            opens_on(day) && self.start <= now && now < self.end
+        } else {
+            (opens_on(day) && now >= self.start) || (opens_on(day.previous()) && now < self.end)
+        }
+    }
+}
+
+fn local_now() -> (Weekday, TimeOfDay) {
+    let now = crate::clock::local_now();
+    (Weekday::ALL[now.weekday as usize % 7], TimeOfDay(now.hour * 60 + now.minute))
+}